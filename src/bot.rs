@@ -1,12 +1,23 @@
 mod tree;
 mod filters;
 mod handlers;
+pub mod auth;
 pub mod commands;
+pub mod scheduler;
+pub mod top;
 
-pub use tree::build_handler;
+pub use tree::{build_handler, ALLOWED_UPDATES};
 pub use handlers::{
+    format_duration,
     make_kb,
-    make_callback_kb
+    make_callback_kb,
+    make_signed_kb,
 };
 
 pub const TARGET_GROUP_ID_KEY: &str = "target_group";
+/// How often a single ad can be bumped to the bottom of the group.
+pub const REPOST_COOLDOWN_SECS: u64 = 12 * 60 * 60;
+/// How often ads of a single user can be re-rendered after their star count changes.
+pub const BADGE_REFRESH_INTERVAL_SECS: u64 = 10 * 60;
+/// How long the /top leaderboard is served from cache.
+pub const TOP_CACHE_SECS: u64 = 10 * 60;
@@ -0,0 +1,50 @@
+use redis::RedisResult;
+use teloxide::prelude::*;
+use crate::bot::commands::CallbackQueryCommand;
+use crate::store::{ads, deals, scheduled};
+use crate::types::{AppConfig, Role, SwappyUser};
+
+/// Moderators can take down any ad, so can everyone above them.
+pub fn is_moderator(config: &AppConfig, user_id: UserId) -> bool {
+    config.has_role(user_id, Role::Moderator)
+}
+
+/// Whether the user may run the command behind an inline button they pressed.
+pub async fn authorize(
+    config: &AppConfig,
+    sw_user: &mut SwappyUser<'_>,
+    cmd: &CallbackQueryCommand,
+) -> RedisResult<bool> {
+    use CallbackQueryCommand::*;
+
+    let group_id = sw_user.group_id;
+    let user_id = sw_user.tg_user.id;
+    let client = &config.redis_client;
+
+    match *cmd {
+        Delete(msg_id) => {
+            if is_moderator(config, user_id) || sw_user.is_author(msg_id).await? {
+                return Ok(true);
+            }
+            // closed ads are not active anymore, so only their record knows the author
+            if ads::load(group_id, msg_id, client)?.is_some_and(|ad| ad.author == user_id) {
+                return Ok(true);
+            }
+            Ok(ads::legacy_author(group_id, msg_id, client)? == Some(user_id))
+        }
+        Edit(msg_id) | Repost(msg_id) | Close(msg_id) => sw_user.is_author(msg_id).await,
+        Renew(msg_id) => Ok(ads::load(group_id, msg_id, client)?.is_some_and(|ad| ad.author == user_id)),
+        PublishNow(id) | Unschedule(id) => {
+            Ok(scheduled::load(group_id, id, client)?.is_some_and(|ad| ad.author.id == user_id))
+        }
+        // leaderboard is public
+        TopPage(_) => Ok(true),
+        ApproveComplaint(_) | RejectComplaint(_) => Ok(is_moderator(config, user_id)),
+        MigrateGroup(..) => Ok(config.has_role(user_id, Role::Owner)),
+        // membership is checked when the group gets selected
+        SelectGroup(_) => Ok(true),
+        ConfirmDeal(id) | DeclineDeal(id) => {
+            Ok(deals::load(group_id, id, client)?.is_some_and(|deal| deal.counterparty == user_id))
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use std::fmt::{Display, Formatter};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use teloxide::macros::BotCommands;
-use teloxide::types::MessageId;
-use crate::bot::commands::CallbackQueryCommand::{Delete, Edit, Repost};
+use teloxide::types::{ChatId, MessageId, UserId};
+use crate::bot::commands::CallbackQueryCommand::{
+    ApproveComplaint, Close, ConfirmDeal, DeclineDeal, Delete, Edit, PublishNow, RejectComplaint, Renew, Repost,
+    MigrateGroup, SelectGroup, TopPage, Unschedule,
+};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
@@ -10,34 +15,90 @@ pub enum SimpleCommand {
     Start,
     /// Узнать количество ваших ⭐️
     MyStars,
+    /// Кому вы вручили ⭐️
+    MyStarred,
+    /// Вкл/выкл уведомления о новых ⭐️
+    StarNotify,
+    /// Участники с наибольшим количеством ⭐️
+    Top,
+    /// Скрыть себя из /top или показать снова
+    TopHide,
+    /// Ваши объявления
+    MyAds,
+    /// Выбрать группу
+    Group,
     /// Описание бота
     Help,
     /// О публикации объявлений
     Posting,
     /// О звёздах
     Stars,
+    /// Правила группы
+    Rules,
     /// О безопасности сделок
     Safety,
     /// О хранимых данных
     PersonalData
 }
 
+#[derive(BotCommands, Clone, Debug)]
+#[command(rename_rule = "lowercase")]
+pub enum ModeratorCommand {
+    /// Show who moderates the bot
+    Staff,
+}
+
 #[derive(BotCommands, Clone, Debug)]
 #[command(rename_rule = "lowercase")]
 pub enum MaintainerCommand {
-    /// Show chat id of currently assigned group
+    /// Show chat ids of served groups, the default one first
     GetGroup,
-    /// Assign new group
-    SetGroup(i64),
     /// Send a test message to a target group
     TestMsg,
+    /// Show settings of the group
+    Settings,
+    /// Change a setting of the group: /set <key> <value>, e.g. /set ad_ttl 7d
+    Set(String),
+    /// Return a setting of the group to the default
+    Reset(String),
+    /// Give a role below your own: /grant <user_id> <role>, or /grant <role> in reply to the user
+    Grant(String),
+    /// Take a role below your own away: /revoke <user_id>, or /revoke in reply to the user
+    Revoke(String),
+}
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(rename_rule = "lowercase")]
+pub enum OwnerCommand {
+    /// Make the group the default one
+    SetGroup(i64),
+    /// Serve one more group
+    AddGroup(i64),
+    /// Stop serving the group, keeping its data
+    RemoveGroup(i64),
+    /// Move stars, complaints, deals and optionally ads from an old group id to the current group
+    MigrateGroup(i64),
+    /// Re-hash stars made with legacy salts
+    RekeyStars,
 }
 
 #[derive(Clone, Debug)]
 pub enum CallbackQueryCommand {
     Delete(MessageId),
     Edit(MessageId),
-    Repost(MessageId)
+    Repost(MessageId),
+    Renew(MessageId),
+    Close(MessageId),
+    PublishNow(u64),
+    Unschedule(u64),
+    TopPage(usize),
+    ApproveComplaint(u64),
+    RejectComplaint(u64),
+    ConfirmDeal(u64),
+    DeclineDeal(u64),
+    /// Old group id and whether ads metadata goes along with stars
+    MigrateGroup(i64, bool),
+    SelectGroup(i64),
 }
 
 impl Display for CallbackQueryCommand {
@@ -46,6 +107,17 @@ impl Display for CallbackQueryCommand {
             Delete(id) => write!(f, "del:{}", id),
             Edit(id) => write!(f, "edit:{}", id),
             Repost(id) => write!(f, "repost:{}", id),
+            Renew(id) => write!(f, "renew:{}", id),
+            Close(id) => write!(f, "close:{}", id),
+            PublishNow(id) => write!(f, "pubnow:{}", id),
+            Unschedule(id) => write!(f, "unsched:{}", id),
+            TopPage(page) => write!(f, "top:{}", page),
+            ApproveComplaint(id) => write!(f, "cok:{}", id),
+            RejectComplaint(id) => write!(f, "cno:{}", id),
+            ConfirmDeal(id) => write!(f, "dok:{}", id),
+            DeclineDeal(id) => write!(f, "dno:{}", id),
+            MigrateGroup(from, with_ads) => write!(f, "mig:{}:{}", from, *with_ads as u8),
+            SelectGroup(group_id) => write!(f, "grp:{}", group_id),
         }
     }
 }
@@ -56,12 +128,146 @@ impl CallbackQueryCommand {
 
         let (cmd, id) = cmd;
 
-        let id = MessageId(id.parse().ok()?);
+        let msg_id = || id.parse().ok().map(MessageId);
         match cmd {
-            "del" => Some(Delete(id)),
-            "edit" => Some(Edit(id)) ,
-            "repost" => Some(Repost(id)),
+            "del" => Some(Delete(msg_id()?)),
+            "edit" => Some(Edit(msg_id()?)) ,
+            "repost" => Some(Repost(msg_id()?)),
+            "renew" => Some(Renew(msg_id()?)),
+            "close" => Some(Close(msg_id()?)),
+            "pubnow" => Some(PublishNow(id.parse().ok()?)),
+            "unsched" => Some(Unschedule(id.parse().ok()?)),
+            "top" => Some(TopPage(id.parse().ok()?)),
+            "cok" => Some(ApproveComplaint(id.parse().ok()?)),
+            "cno" => Some(RejectComplaint(id.parse().ok()?)),
+            "dok" => Some(ConfirmDeal(id.parse().ok()?)),
+            "dno" => Some(DeclineDeal(id.parse().ok()?)),
+            "mig" => {
+                let (from, with_ads) = id.split_once(':')?;
+                Some(MigrateGroup(from.parse().ok()?, with_ads == "1"))
+            }
+            "grp" => Some(SelectGroup(id.parse().ok()?)),
             _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Hex chars of the signature appended to callback data
+const SIGNATURE_LEN: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum CallbackError {
+    /// Not a command at all, or a command this version doesn't know
+    Unknown,
+    /// Buttons sent before callback data got signed and bound to a group
+    Unsigned,
+    /// Signed for another user or tampered with
+    BadSignature,
+}
+
+impl CallbackQueryCommand {
+    /// Callback data bound to the group it's about and to the user who is going to press the button.
+    pub fn sign(&self, group_id: ChatId, user_id: UserId, key: &[u8]) -> String {
+        let payload = format!("{}@{}", self, group_id);
+        let signature = signature(&payload, user_id, key);
+        format!("{payload}.{signature}")
+    }
+
+    /// Parses callback data produced by [`CallbackQueryCommand::sign`] for the given user.
+    pub fn verify(data: &str, user_id: UserId, key: &[u8]) -> Result<(Self, ChatId), CallbackError> {
+        let Some((payload, sig)) = data.rsplit_once('.') else {
+            return match Self::parse(data) {
+                Some(_) => Err(CallbackError::Unsigned),
+                None => Err(CallbackError::Unknown),
+            }
+        };
+
+        let (cmd, group_id) = payload.split_once('@').unwrap_or((payload, ""));
+        let cmd = Self::parse(cmd).ok_or(CallbackError::Unknown)?;
+        // buttons signed before they got bound to a group are as good as unsigned
+        if group_id.is_empty() { return Err(CallbackError::Unsigned) }
+        let group_id = group_id.parse().map_err(|_| CallbackError::Unknown)?;
+        let sig = hex::decode(sig).ok()
+            .filter(|sig| sig.len() * 2 == SIGNATURE_LEN)
+            .ok_or(CallbackError::BadSignature)?;
+        mac(payload, user_id, key).verify_truncated_left(&sig)
+            .map_err(|_| CallbackError::BadSignature)?;
+
+        Ok((cmd, ChatId(group_id)))
+    }
+}
+
+fn mac(payload: &str, user_id: UserId, key: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(b"callback");
+    mac.update(&user_id.0.to_be_bytes());
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn signature(payload: &str, user_id: UserId, key: &[u8]) -> String {
+    let mut sig = hex::encode(mac(payload, user_id, key).finalize().into_bytes());
+    sig.truncate(SIGNATURE_LEN);
+    sig
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{ChatId, MessageId, UserId};
+    use super::{CallbackError, CallbackQueryCommand};
+    use super::CallbackQueryCommand::*;
+
+    const KEY: &[u8] = b"makaroshki";
+    const GROUP: ChatId = ChatId(-1001234567890);
+
+    #[test]
+    fn signed_data_is_verified() {
+        let data = Delete(MessageId(42)).sign(GROUP, UserId(1), KEY);
+
+        assert!(data.len() <= 64);
+        assert!(matches!(
+            CallbackQueryCommand::verify(&data, UserId(1), KEY),
+            Ok((Delete(MessageId(42)), GROUP))
+        ));
+    }
+
+    #[test]
+    fn data_is_bound_to_user() {
+        let data = Delete(MessageId(42)).sign(GROUP, UserId(1), KEY);
+        assert_eq!(CallbackQueryCommand::verify(&data, UserId(2), KEY).err(), Some(CallbackError::BadSignature));
+    }
+
+    #[test]
+    fn tampered_data_is_rejected() {
+        let data = Delete(MessageId(42)).sign(GROUP, UserId(1), KEY).replace("42", "43");
+        assert_eq!(CallbackQueryCommand::verify(&data, UserId(1), KEY).err(), Some(CallbackError::BadSignature));
+
+        let data = Delete(MessageId(42)).sign(GROUP, UserId(1), KEY).replace("@-100", "@-200");
+        assert_eq!(CallbackQueryCommand::verify(&data, UserId(1), KEY).err(), Some(CallbackError::BadSignature));
+
+        let data = format!("del:42@{}.not-hex", GROUP);
+        assert_eq!(CallbackQueryCommand::verify(&data, UserId(1), KEY).err(), Some(CallbackError::BadSignature));
+    }
+
+    #[test]
+    fn legacy_and_unknown_data_are_told_apart() {
+        assert_eq!(CallbackQueryCommand::verify("del:42", UserId(1), KEY).err(), Some(CallbackError::Unsigned));
+        assert_eq!(CallbackQueryCommand::verify("42", UserId(1), KEY).err(), Some(CallbackError::Unknown));
+        assert_eq!(CallbackQueryCommand::verify("nope:42.abc", UserId(1), KEY).err(), Some(CallbackError::Unknown));
+    }
+
+    #[test]
+    fn migration_data_is_parsed() {
+        let data = MigrateGroup(-1001234567890, true).sign(GROUP, UserId(1), KEY);
+        assert!(matches!(
+            CallbackQueryCommand::verify(&data, UserId(1), KEY),
+            Ok((MigrateGroup(-1001234567890, true), GROUP))
+        ));
+    }
+
+    #[test]
+    fn longest_data_fits_telegram_limit() {
+        let data = Unschedule(u64::MAX).sign(GROUP, UserId(u64::MAX), KEY);
+        assert!(data.len() <= 64);
+    }
+}
@@ -1,8 +1,9 @@
 use std::sync::Arc;
 use teloxide::prelude::Message;
 use teloxide::types::{Me, MessageKind};
-use crate::types::AppConfig;
+use crate::types::{AppConfig, Role};
 
+#[allow(clippy::redundant_pattern_matching, clippy::match_like_matches_macro)]
 pub fn me_added_to_group(message: Message, me: Me) -> bool {
     if let Some(new_members) = message.new_chat_members() {
         new_members.iter().any(|member| member.id == me.id)
@@ -13,10 +14,27 @@ pub fn me_added_to_group(message: Message, me: Me) -> bool {
     }
 }
 
+pub fn msg_from_moderator(config: Arc<AppConfig>, message: Message) -> bool {
+    msg_from_role(&config, &message, Role::Moderator)
+}
+
 pub fn msg_from_maintainer(config: Arc<AppConfig>, message: Message) -> bool {
-    message.from.map(|user| user.id == config.bot_maintainer).unwrap_or_default()
+    msg_from_role(&config, &message, Role::Maintainer)
+}
+
+pub fn msg_from_owner(config: Arc<AppConfig>, message: Message) -> bool {
+    msg_from_role(&config, &message, Role::Owner)
 }
 
+fn msg_from_role(config: &AppConfig, message: &Message, role: Role) -> bool {
+    message.from.as_ref().is_some_and(|user| config.has_role(user.id, role))
+}
+
+#[allow(clippy::match_like_matches_macro)]
 pub fn has_shared_users(message: Message) -> bool {
     if let MessageKind::UsersShared(_) = message.kind { true } else { false }
-}
\ No newline at end of file
+}
+/// Plain text sent to the bot in private, e.g. an answer to its question.
+pub fn is_private_text(message: Message) -> bool {
+    message.chat.is_private() && message.text().is_some_and(|text| !text.starts_with('/'))
+}
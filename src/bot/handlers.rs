@@ -1,20 +1,54 @@
 use super::commands::*;
-use super::TARGET_GROUP_ID_KEY;
-use crate::store::{get_star_count, give_star};
-use crate::types::{AppConfig, ToSwappyUser};
-use redis::Commands;
+use super::BADGE_REFRESH_INTERVAL_SECS;
+use super::auth::authorize;
+use super::scheduler::{cancel_scheduled, NOT_A_MEMBER};
+use super::top::{self, TOP_PAGE_SIZE};
+use crate::store::{self, ads, badges, complaints, deals, groups, members, migration, roles, settings, RekeyReport, MONTH_SECS, get_star_count, give_star, star_notifications_enabled, take_star, toggle_star_notifications};
+use crate::store::deals::ProposalError;
+use crate::store::ads::AdStatus;
+use crate::store::migration::MigrationReport;
+use crate::store::scheduled::{self, ScheduledAd};
+use crate::store::starred::{self, StarredUser};
+use crate::site::{
+    format_deals,
+    make_edit_url,
+    make_report_kb,
+    publish_scheduled,
+    refresh_ads,
+    render_closed_ad,
+    render_closed_text,
+    republish_ad,
+    WARNING_MARK,
+};
+use crate::types::{AppConfig, GroupSettings, Role, SettingError, StarRefusal, SwappyUser, ToSwappyUser};
+use redis::RedisResult;
 use std::fmt::Display;
 use std::sync::Arc;
 use teloxide::dispatching::dialogue::GetChatId;
-use teloxide::payloads::AnswerCallbackQuerySetters;
-use teloxide::payloads::EditMessageTextSetters;
+use teloxide::payloads::{AnswerCallbackQuerySetters, CopyMessageSetters};
+use teloxide::payloads::{EditMessageReplyMarkupSetters, EditMessageTextSetters};
 use teloxide::payloads::SendMessageSetters;
-use teloxide::prelude::{CallbackQuery, ChatId, Message, Requester};
-use teloxide::types::{ButtonRequest, ChatKind, InlineKeyboardButton, InlineKeyboardMarkup, KeyboardButton, KeyboardButtonRequestUsers, KeyboardMarkup, MessageKind, ParseMode, RequestId};
+use teloxide::prelude::{CallbackQuery, ChatId, Message, Requester, UserId};
+use teloxide::types::{ButtonRequest, ChatKind, ChatMemberUpdated, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, KeyboardButton, KeyboardButtonRequestUsers, KeyboardMarkup, MessageId, MessageKind, ParseMode, RequestId, User, WebAppInfo};
 use teloxide::utils::command::BotCommands;
-use teloxide::{Bot, RequestError};
+use teloxide::utils::html;
+use teloxide::{ApiError, Bot, RequestError};
 use ButtonRequest::RequestUsers;
 
+/// Request ids of the reply keyboard buttons sharing users with the bot.
+const GIVE_STAR_REQUEST_ID: RequestId = RequestId(1);
+const TAKE_STAR_REQUEST_ID: RequestId = RequestId(2);
+const COMPLAINT_REQUEST_ID: RequestId = RequestId(3);
+const DEAL_REQUEST_ID: RequestId = RequestId(4);
+
+/// How long the bot waits for the reason of a complaint.
+const COMPLAINT_REASON_TIMEOUT_SECS: u64 = 60 * 60;
+
+/// Deals a member can propose a day, so counterparties don't get spammed with proposals.
+const MAX_DEAL_PROPOSALS_PER_DAY: usize = 5;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
 pub async fn handle_added_to_group(
     bot: Bot,
     config: Arc<AppConfig>,
@@ -35,9 +69,11 @@ pub async fn handle_added_to_group(
     } else { String::from("Somebody") };
 
     let grp_id = message.chat.id;
-    let text = format!("{user} added me to {grp_title} \\(`{grp_id}`\\)");
-    bot.send_message(config.bot_maintainer, text).parse_mode(ParseMode::MarkdownV2)
-        .await?;
+    let text = format!("{user} added me to {grp_title} \\(`{grp_id}`\\)\n\nServe it with `/addgroup {grp_id}`");
+    // only the owner can serve it
+    if let Err(e) = bot.send_message(config.bot_maintainer, &text).parse_mode(ParseMode::MarkdownV2).await {
+        log::warn!("failed to tell the owner about the new group: {}", e.to_string());
+    }
 
     Ok(())
 }
@@ -48,13 +84,56 @@ pub async fn handle_callback_query(
     config: Arc<AppConfig>,
 ) -> Result<(), RequestError> {
     if let Some(ref data) = callback_query.data {
-        use CallbackQueryCommand::{Delete, Edit, Repost};
-        let cmd = CallbackQueryCommand::parse(data);
-        if cmd.is_none() { todo!("return some kinda error") }
+        use CallbackQueryCommand::{
+            ApproveComplaint, Close, ConfirmDeal, DeclineDeal, Delete, Edit, MigrateGroup, PublishNow,
+            RejectComplaint, Renew, Repost, SelectGroup, TopPage, Unschedule,
+        };
+        let (cmd, group_id) = match CallbackQueryCommand::verify(data, callback_query.from.id, config.callback_key()) {
+            Ok(res) => res,
+            Err(e) => {
+                let text = match e {
+                    CallbackError::Unsigned => "Эта кнопка устарела. Свежие кнопки управления \
+                        объявлениями пришлёт команда /myads",
+                    CallbackError::BadSignature => "Эта кнопка не для вас",
+                    CallbackError::Unknown => "Неизвестная команда",
+                };
+                return bot.answer_callback_query(callback_query.id)
+                    .text(text)
+                    .await.map(|_| ());
+            }
+        };
 
-        let group_id = config.group_id();
-        match cmd.unwrap() {
+        if !config.is_registered(group_id) {
+            return bot.answer_callback_query(callback_query.id)
+                .text("Бот больше не обслуживает эту группу")
+                .await.map(|_| ());
+        }
+
+        let mut sw_user = callback_query.from.clone().in_group(&config, group_id).await;
+        let text = match authorize(&config, &mut sw_user, &cmd).await {
+            Ok(true) => None,
+            Ok(false) => Some("Это объявление не ваше"),
+            Err(e) => {
+                log::error!("authorization failed: {}", e.to_string());
+                Some("Something went wrong")
+            }
+        };
+        if let Some(text) = text {
+            return bot.answer_callback_query(callback_query.id)
+                .text(text)
+                .await.map(|_| ());
+        }
+
+        let key = config.callback_key();
+        match cmd {
             Delete(msg_id) => {
+                // moderators take down ads of others
+                let author = match ads::load(group_id, msg_id, &config.redis_client) {
+                    Ok(Some(ad)) => ad.author,
+                    _ => ads::legacy_author(group_id, msg_id, &config.redis_client).ok().flatten()
+                        .unwrap_or(callback_query.from.id),
+                };
+
                 let res = bot.delete_message(group_id, msg_id).await;
 
                 if res.is_err() {
@@ -63,6 +142,10 @@ pub async fn handle_callback_query(
                         .await.map(|_| ());
                 }
 
+                if let Err(e) = ads::forget(group_id, author, msg_id, &config.redis_client) {
+                    log::error!("failed to forget ad: {}", e.to_string());
+                }
+
                 // assuming some filtering has been done previously
                 let msg = callback_query.regular_message().unwrap();
                 let chat_id = callback_query.chat_id().unwrap();
@@ -72,10 +155,317 @@ pub async fn handle_callback_query(
                     .parse_mode(ParseMode::Html)
                     .await?;
             }
-            Edit(_) => {
-                // everything happens in webapp
+            Edit(msg_id) => {
+                // everything happens in webapp, just hand out the link to it
+                let chat_id = callback_query.chat_id().unwrap();
+                let url = make_edit_url(&config, group_id, msg_id);
+                bot.send_message(chat_id, "Редактор объявления откроется по кнопке ниже")
+                    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                        InlineKeyboardButton::web_app("Редактировать ✏️", WebAppInfo { url }),
+                    ]]))
+                    .await?;
+            }
+            Repost(msg_id) => {
+                if let Some(secs) = sw_user.repost_cooldown(msg_id).await.unwrap_or_default() {
+                    return bot.answer_callback_query(callback_query.id)
+                        .text(format!("Поднять объявление можно будет через {}", format_duration(secs)))
+                        .await.map(|_| ());
+                }
+
+                let new_id = match bot.copy_message(group_id, group_id, msg_id).await {
+                    Ok(id) => id,
+                    Err(e) => {
+                        log::error!("failed to repost ad: {}", e.to_string());
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Не удалось поднять объявление")
+                            .await.map(|_| ());
+                    }
+                };
+
+                // keep a single copy of the ad in the group, roll back if the old one can't go
+                if let Err(e) = bot.delete_message(group_id, msg_id).await {
+                    log::error!("failed to delete reposted ad: {}", e.to_string());
+                    bot.delete_message(group_id, new_id).await?;
+                    return bot.answer_callback_query(callback_query.id)
+                        .text("Не удалось поднять объявление")
+                        .await.map(|_| ());
+                }
+
+                if let Err(e) = move_authorship(&mut sw_user, msg_id, new_id).await {
+                    log::error!("failed to move authorship: {}", e.to_string());
+                }
+                let stored = match ads::rename(group_id, msg_id, new_id, &config.redis_client) {
+                    Ok(stored) => stored,
+                    Err(e) => {
+                        log::error!("failed to move ad: {}", e.to_string());
+                        false
+                    }
+                };
+
+                // assuming some filtering has been done previously
+                let msg = callback_query.regular_message().unwrap();
+                let chat_id = callback_query.chat_id().unwrap();
+                let keeping = msg.reply_markup().map(|kb| {
+                    kb.inline_keyboard.iter().flatten()
+                        .any(|butt| matches!(butt.kind, InlineKeyboardButtonKind::WebApp(_)))
+                }).unwrap_or_default();
+                bot.edit_message_reply_markup(chat_id, msg.id)
+                    .reply_markup(make_report_kb(&config, group_id, new_id, sw_user.tg_user.id, stored || keeping))
+                    .await?;
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text("Объявление поднято")
+                    .await.map(|_| ());
+            }
+            Close(msg_id) => {
+                let stored = ads::load(group_id, msg_id, &config.redis_client).unwrap_or_else(|e| {
+                    log::error!("failed to load ad: {}", e.to_string());
+                    None
+                });
+
+                // assuming some filtering has been done previously
+                let msg = callback_query.regular_message().unwrap();
+                let chat_id = callback_query.chat_id().unwrap();
+                let text = match &stored {
+                    Some(ad) => render_closed_ad(&ad.form, &mut sw_user).await,
+                    None => render_closed_text(msg.text().unwrap_or_default()),
+                };
+                if let Err(e) = bot.edit_message_text(group_id, msg_id, text)
+                    .parse_mode(ParseMode::Html)
+                    .await {
+                    log::error!("failed to close ad: {}", e.to_string());
+                    return bot.answer_callback_query(callback_query.id)
+                        .text("Не удалось закрыть объявление")
+                        .await.map(|_| ());
+                }
+
+                // closed ads are not active anymore, the author of a legacy one is kept aside,
+                // so they can still take it down
+                let closed = match stored {
+                    Some(mut ad) => {
+                        ad.status = AdStatus::Closed;
+                        ad.touch();
+                        ads::save(group_id, &ad, &config.redis_client)
+                    }
+                    None => ads::set_legacy_author(group_id, sw_user.tg_user.id, msg_id, &config.redis_client),
+                };
+                if let Err(e) = closed
+                    .and_then(|_| ads::unset_author(group_id, sw_user.tg_user.id, msg_id, &config.redis_client)) {
+                    log::error!("failed to save ad: {}", e.to_string());
+                }
+                if let Err(e) = ads::unmark_published(group_id, msg_id, &config.redis_client) {
+                    log::error!("failed to unmark ad: {}", e.to_string());
+                }
+
+                bot.edit_message_reply_markup(chat_id, msg.id)
+                    .reply_markup(make_signed_kb(
+                        vec![vec![("Снять 🗑️".to_string(), Delete(msg_id))]],
+                        group_id,
+                        sw_user.tg_user.id,
+                        key,
+                    ))
+                    .await?;
+                bot.send_message(chat_id, "Поздравляем со сделкой! Если всё прошло хорошо, вручите \
+                    ⭐️ вашему контрагенту кнопкой под полем ввода.")
+                    .reply_markup(make_start_kb())
+                    .await?;
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text("Объявление закрыто")
+                    .await.map(|_| ());
+            }
+            PublishNow(id) => {
+                let Some(scheduled_ad) = take_scheduled(&bot, &callback_query, &config, group_id, id).await? else {
+                    return Ok(());
+                };
+
+                let requeue = |scheduled_ad: &ScheduledAd| {
+                    scheduled::save(group_id, scheduled_ad, &config.redis_client)
+                        .inspect_err(|e| log::error!("failed to requeue scheduled ad: {}", e.to_string()))
+                };
+
+                match sw_user.is_group_member().await {
+                    Ok(true) => {} // continue
+                    Ok(false) => {
+                        cancel_scheduled(&config, &scheduled_ad, NOT_A_MEMBER).await;
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Вы больше не состоите в группе")
+                            .await.map(|_| ());
+                    }
+                    Err(e) => {
+                        log::error!("member check failed: {}", e.to_string());
+                        let _ = requeue(&scheduled_ad);
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Something went wrong")
+                            .await.map(|_| ());
+                    }
+                }
+
+                match sw_user.reserve_posting_cooldown().await {
+                    Ok(None) => {} // continue
+                    Ok(Some(wait)) => {
+                        let _ = requeue(&scheduled_ad);
+                        return bot.answer_callback_query(callback_query.id)
+                            .text(format!("Новое объявление можно будет опубликовать через {}", format_duration(wait)))
+                            .await.map(|_| ());
+                    }
+                    Err(e) => {
+                        log::error!("failed to reserve posting cooldown: {}", e.to_string());
+                        let _ = requeue(&scheduled_ad);
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Something went wrong")
+                            .await.map(|_| ());
+                    }
+                }
+
+                if let Err(e) = publish_scheduled(&config, &scheduled_ad, &mut sw_user).await {
+                    log::error!("failed to publish scheduled ad: {}", e.to_string());
+                    if let Err(e) = sw_user.release_posting_cooldown().await {
+                        log::error!("failed to release posting cooldown: {}", e.to_string());
+                    }
+                    let _ = requeue(&scheduled_ad);
+                    return bot.answer_callback_query(callback_query.id)
+                        .text("Не удалось опубликовать объявление, оно осталось в очереди")
+                        .await.map(|_| ());
+                }
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text("Объявление опубликовано")
+                    .await.map(|_| ());
+            }
+            Unschedule(id) => {
+                let Some(scheduled_ad) = take_scheduled(&bot, &callback_query, &config, group_id, id).await? else {
+                    return Ok(());
+                };
+
+                // assuming some filtering has been done previously
+                let msg = callback_query.regular_message().unwrap();
+                let chat_id = callback_query.chat_id().unwrap();
+                bot.edit_message_text(chat_id, msg.id,
+                                      format!("Публикация отменена:\n\n{}", scheduled_ad.form))
+                    .parse_mode(ParseMode::Html)
+                    .await?;
+            }
+            Renew(msg_id) => {
+                let ad = match ads::load(group_id, msg_id, &config.redis_client) {
+                    Ok(Some(ad)) if ad.status == AdStatus::Expired => ad,
+                    Ok(_) => {
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Это объявление нельзя продлить")
+                            .await.map(|_| ());
+                    }
+                    Err(e) => {
+                        log::error!("failed to load ad: {}", e.to_string());
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Something went wrong")
+                            .await.map(|_| ());
+                    }
+                };
+
+                match sw_user.check_posting_limits().await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(rejection)) => {
+                        return bot.answer_callback_query(callback_query.id)
+                            .text(rejection.to_string())
+                            .show_alert(true)
+                            .await.map(|_| ());
+                    }
+                    Err(e) => {
+                        log::error!("redis query failed: {}", e.to_string());
+                        return bot.answer_callback_query(callback_query.id)
+                            .text("Something went wrong")
+                            .await.map(|_| ());
+                    }
+                }
+
+                if let Err(e) = republish_ad(&config, ad, &mut sw_user).await {
+                    log::error!("failed to renew ad: {}", e.to_string());
+                    return bot.answer_callback_query(callback_query.id)
+                        .text("Не удалось продлить объявление")
+                        .await.map(|_| ());
+                }
+
+                // assuming some filtering has been done previously
+                let msg = callback_query.regular_message().unwrap();
+                let chat_id = callback_query.chat_id().unwrap();
+                bot.edit_message_reply_markup(chat_id, msg.id).await?;
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text("Объявление опубликовано заново")
+                    .await.map(|_| ());
+            }
+            TopPage(page) => {
+                let (text, kb) = render_top_page(&config, group_id, page, callback_query.from.id).await;
+                let msg = callback_query.regular_message().unwrap();
+                let res = bot.edit_message_text(msg.chat.id, msg.id, text)
+                    .parse_mode(ParseMode::Html)
+                    .reply_markup(kb)
+                    .await;
+
+                match res {
+                    Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            ApproveComplaint(id) | RejectComplaint(id) => {
+                let approve = matches!(cmd, ApproveComplaint(_));
+                let text = resolve_complaint(&bot, &config, group_id, id, approve).await;
+
+                let msg = callback_query.regular_message().unwrap();
+                bot.edit_message_reply_markup(msg.chat.id, msg.id).await?;
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text(text)
+                    .await.map(|_| ());
+            }
+            MigrateGroup(from, with_ads) => {
+                let to = group_id;
+                let client = config.redis_client.clone();
+                let res = tokio::task::spawn_blocking(move || migration::migrate(ChatId(from), to, with_ads, &client))
+                    .await.expect("migration shouldn't panic");
+
+                let text = match res {
+                    Ok(report) => format!("Migrated from {} to {}\n\n{}", from, to, format_migration(&report, with_ads)),
+                    Err(e) => format!("Migration failed: {}", e),
+                };
+
+                let msg = callback_query.regular_message().unwrap();
+                bot.edit_message_reply_markup(msg.chat.id, msg.id).await?;
+                bot.send_message(msg.chat.id, text).await?;
+            }
+            SelectGroup(_) => {
+                let user_id = callback_query.from.id;
+                let text = match bot.get_chat_member(group_id, user_id).await {
+                    Ok(member) if member.is_present() => {
+                        match groups::select(user_id, group_id, &config.redis_client) {
+                            Ok(_) => "Группа выбрана",
+                            Err(e) => {
+                                log::error!("failed to select group: {}", e.to_string());
+                                "Something went wrong"
+                            }
+                        }
+                    }
+                    _ => "Вы не состоите в этой группе",
+                };
+
+                let msg = callback_query.regular_message().unwrap();
+                bot.edit_message_reply_markup(msg.chat.id, msg.id).await?;
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text(text)
+                    .await.map(|_| ());
+            }
+            ConfirmDeal(id) | DeclineDeal(id) => {
+                let confirm = matches!(cmd, ConfirmDeal(_));
+                let text = resolve_deal(&bot, &config, group_id, id, confirm).await;
+
+                let msg = callback_query.regular_message().unwrap();
+                bot.edit_message_reply_markup(msg.chat.id, msg.id).await?;
+
+                return bot.answer_callback_query(callback_query.id)
+                    .text(text)
+                    .await.map(|_| ());
             }
-            Repost(_) => {}
         }
     }
 
@@ -88,6 +478,13 @@ pub async fn handle_simple_command(
     msg: Message,
     command: SimpleCommand,
 ) -> Result<(), RequestError> {
+    // commands sent in a group are about it, in private about the group the user chose
+    let group_id = if config.is_registered(msg.chat.id) {
+        msg.chat.id
+    } else {
+        config.group_of(msg.from.as_ref().unwrap().id)
+    };
+
     let mut kb: Option<KeyboardMarkup> = None;
     let text = match command {
         SimpleCommand::Start => {
@@ -96,25 +493,92 @@ pub async fn handle_simple_command(
             Также можно раздавать и получать ⭐.\n\n\
             Подробнее о публикации объявлений: /posting\n\
             Подробнее о звёздах: /stars\n\
+            Правила группы: /rules\n\
             Советы о совершении сделок: /safety\n\
-            О хранении данных: /personaldata".to_string()
+            О хранении данных: /personaldata\n\
+            Выбрать группу, если вы состоите в нескольких: /group".to_string()
         }
         SimpleCommand::Help => {
-            if msg.from.unwrap().id == config.bot_maintainer {
-                format!(
-                    "{}\n\n{}",
-                    SimpleCommand::descriptions(),
-                    MaintainerCommand::descriptions(),
-                )
-            } else {
-                SimpleCommand::descriptions().to_string()
+            let user_id = msg.from.unwrap().id;
+            let mut help = vec![SimpleCommand::descriptions().to_string()];
+            if config.has_role(user_id, Role::Moderator) {
+                help.push(ModeratorCommand::descriptions().to_string());
+            }
+            if config.has_role(user_id, Role::Maintainer) {
+                help.push(MaintainerCommand::descriptions().to_string());
+            }
+            if config.has_role(user_id, Role::Owner) {
+                help.push(OwnerCommand::descriptions().to_string());
             }
+            help.join("\n\n")
         }
+        SimpleCommand::MyAds => return send_my_ads(&bot, &config, group_id, msg).await,
+        SimpleCommand::Group => return send_groups(&bot, &config, group_id, msg).await,
         SimpleCommand::MyStars => {
-            let sc = get_star_count(msg.from.unwrap().id, config.group_id(),
+            let user_id = msg.from.unwrap().id;
+            let star_window = config.settings_of(group_id).star_window;
+            let sc = get_star_count(user_id, group_id, star_window,
                            &config.redis_client).expect("").to_string();
 
-            format!("У вас {}⭐", sc)
+            let wc = match complaints::get_warning_count(user_id, group_id, &config.redis_client) {
+                Ok(wc) => wc,
+                Err(e) => {
+                    log::error!("failed to count warnings: {}", e.to_string());
+                    return bot.send_message(msg.chat.id, "Что-то пошло не так, попробуйте позднее")
+                        .await.map(|_| ());
+                }
+            };
+            let warnings = if wc > 0 {
+                format!("\n{} Одобренных жалоб на вас: {}", WARNING_MARK, wc)
+            } else { String::default() };
+
+            let dc = match deals::get_deal_count(user_id, group_id, &config.redis_client) {
+                Ok(dc) => dc,
+                Err(e) => {
+                    log::error!("failed to count deals: {}", e.to_string());
+                    return bot.send_message(msg.chat.id, "Что-то пошло не так, попробуйте позднее")
+                        .await.map(|_| ());
+                }
+            };
+            let deals = if dc > 0 { format!(", {}", format_deals(dc)) } else { String::default() };
+
+            match star_window {
+                Some(window) if window >= MONTH_SECS => format!(
+                    "У вас {}⭐ за последние {} мес.{}{}", sc, window / MONTH_SECS, deals, warnings,
+                ),
+                Some(window) => format!(
+                    "У вас {}⭐ за последние {} дн.{}{}", sc, window.div_ceil(DAY_SECS), deals, warnings,
+                ),
+                None => format!("У вас {}⭐{}{}", sc, deals, warnings),
+            }
+        }
+        SimpleCommand::MyStarred => return send_my_starred(&bot, &config, group_id, msg).await,
+        SimpleCommand::Top => {
+            let (text, kb) = render_top_page(&config, group_id, 0, msg.from.unwrap().id).await;
+            return bot.send_message(msg.chat.id, text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(kb)
+                .await.map(|_| ());
+        }
+        SimpleCommand::TopHide => {
+            match crate::store::top::toggle_hidden(group_id, msg.from.unwrap().id, &config.redis_client) {
+                Ok(true) => "Вы скрыты из /top. Показать снова: /tophide".to_string(),
+                Ok(false) => "Вы снова видны в /top".to_string(),
+                Err(e) => {
+                    log::error!("failed to toggle leaderboard visibility: {}", e.to_string());
+                    "Что-то пошло не так, попробуйте позднее".to_string()
+                }
+            }
+        }
+        SimpleCommand::StarNotify => {
+            match toggle_star_notifications(msg.from.unwrap().id, group_id, &config.redis_client) {
+                Ok(true) => "Уведомления о новых звёздах включены".to_string(),
+                Ok(false) => "Уведомления о новых звёздах выключены. Включить обратно: /starnotify".to_string(),
+                Err(e) => {
+                    log::error!("failed to toggle star notifications: {}", e.to_string());
+                    "Что-то пошло не так, попробуйте позднее".to_string()
+                }
+            }
         }
         SimpleCommand::Stars => {
             "Чтобы вручить кому-нибудь звезду, воспользуйтесь кнопкой под полем ввода. Если кнопку не \
@@ -122,13 +586,19 @@ pub async fn handle_simple_command(
             поделиться ими с ботом. Можно выбрать до 10 пользователей за раз. Каждый получит от вас \
             звезду.\n\n\
             Звёзды (если есть) отображаются рядом с вашим именем в публикуемых вами объявлениях. Ко\
-            личество имеющихся у вас звёзд можно узнать командой /mystars.\n\n\
+            личество имеющихся у вас звёзд можно узнать командой /mystars. О каждой новой звезде бот \
+            сообщит вам, не раскрывая, от кого она. Отключить эти уведомления можно командой \
+            /starnotify. Участники с наибольшим количеством звёзд - в /top, скрыть себя оттуда можно \
+            командой /tophide.\n\n\
             Звёзды можно получать от и давать только другим участникам группы. Нельзя вручить звезду \
-            себе (как бы ни хотелось). Для того, чтобы вручить кому-то звезду, не обязательно обмени\
+            себе (как бы ни хотелось). Вручать звёзды могут запретить совсем новым участникам или тем, \
+            у кого мало своих звёзд, а в сутки можно вручить ограниченное число звёзд - если звезда \
+            не вручена, бот объяснит почему. Для того, чтобы вручить кому-то звезду, не обязательно обмени\
             ваться с этим человеком. Если вы готовы в будущем обменяться с кем-то - это хороший повод \
             вручить звезду.\n\n\
-            Одному человеку звезду дать можно только один раз. Забирать звёзды пока нельзя, но будет \
-            можно в дальнейшем.\n\n\
+            Одному человеку звезду дать можно только один раз. Если звёзды в группе учитываются только \
+            за последние месяцы, повторное вручение продлевает вашу звезду. Передумали - заберите \
+            звезду кнопкой \"Забрать ⭐️\", выбрав тех же пользователей.\n\n\
             Звёзды призваны облегчить принятие решения о сделке в ситуациях, когда у вас нет общих \
             чатов с человеком, и когда вы с ним не знакомы. Тем не менее, они не ставят своей целью \
             заменить ваш здравый смысл, поэтому не полагайтесь только на них.".to_string()
@@ -138,13 +608,26 @@ pub async fn handle_simple_command(
             Откроется мини-приложение с формой, которую нужно будет заполнить, после чего нажать \
             кнопку \"Опубликовать\". После вашего подтверждения бот опубликует сообщение в группе \
             и пришлёт в этот чат его копию с кнопками управления вашим объявлением. В сообщении \
-            будет содержаться ссылка на чат с вами, количество ваших звезд и само объявление.\n\n\
-            По умолчанию, бот не запоминает информацию об объявлениях, поэтому объявление можно будет \
-            только удалить. Чтобы включить возможность редактировать объявления, вы можете включить \
-            запоминание данных опубликованных объявлений. Для этого откройте мини-приложение и в его \
-            настройках включите запоминание данных форм. Все опубликованные в дальнейшем сообщения \
-            будет возможно редактировать. Также, если эта настройка включена, следующие публикации \
-            будут предзаполнены данными последнего объявления.".to_string()
+            будет содержаться ссылка на чат с вами, количество ваших звезд и само объявление. Если \
+            копия потерялась, команда /myads пришлёт копии всех ваших активных объявлений.\n\n\
+            Бот запоминает данные опубликованных объявлений, поэтому их можно редактировать кнопкой \
+            \"Редактировать ✏️\" под копией объявления. Если в настройках мини-приложения включено \
+            запоминание данных форм, следующие публикации будут предзаполнены данными последнего \
+            объявления.\n\n\
+            Публикацию можно запланировать на удобное время, до недели вперёд. Тогда бот пришлёт \
+            сообщение с кнопками \"Опубликовать сейчас 🚀\" и \"Отменить ❌\", а в назначенное время \
+            опубликует объявление как обычно.\n\n\
+            Опубликованное объявление можно поднять в конец группы кнопкой \"Поднять ⬆️\" под его \
+            копией, но не чаще, чем раз в 12 часов.\n\n\
+            Если в группе ограничен срок публикации объявлений, по его истечении бот снимет \
+            объявление из группы и пришлёт вам сообщение с кнопкой \"Продлить 🔄\", которая \
+            опубликует его заново.\n\n\
+            Когда сделка состоится, нажмите \"Сделка состоялась ✅\": объявление останется в группе \
+            зачёркнутым с отметкой о закрытии, а бот предложит вручить ⭐️ вашему контрагенту.".to_string()
+        }
+        SimpleCommand::Rules => {
+            let rules = config.settings_of(group_id).rules;
+            if rules.is_empty() { "Правила группы пока не заданы".to_string() } else { rules }
         }
         SimpleCommand::Safety => {
             "Этот раздел посвящается обменам с незнакомыми людьми. О том, как вытрясти долги с людей, \
@@ -157,16 +640,25 @@ pub async fn handle_simple_command(
             При обмене наличными главное, что нужно помнить, - кто-то будет знать, где и когда вы будете \
             и сколько именно денег будет у вас в кармане. Планируйте встречи соответствующе: лучше \
             в светлое время суток, не в поле и не в безлюдных местах, возьмите с собой на встречу \
-            кого-нибудь ещё.".to_string()
+            кого-нибудь ещё.\n\n\
+            После удачной сделки отметьте её кнопкой \"Сделка 🤝\": когда второй участник её \
+            подтвердит, количество сделок появится рядом со звёздами в объявлениях у вас обоих. \
+            Сделки с одним и тем же человеком засчитываются один раз.\n\n\
+            Если вас обманули, воспользуйтесь кнопкой \"Пожаловаться ⚠️\" под полем ввода (если её \
+            не видно, поможет /start). Одобренные модераторами жалобы отмечаются знаком ⚠️ рядом с \
+            именем человека в его объявлениях.".to_string()
         }
         SimpleCommand::PersonalData => {
             "Что хранится в базе бота?\n\nБот хранит информацию о том, какие объявления ваши, чтобы \
-            никто кроме вас не смог их отредактировать. Эта информация не зашифрована, потому что \
-            она является публичной. Также хранится информация о звёздах. Эта информация хранится в \
-            зашифрованном (точнее, хешированном с секретной солью) виде.\n\n\
-            Где будут храниться данные моих объявлений, если я включу соответствующую настройку?\n\n\
+            никто кроме вас не смог их отредактировать, а также данные опубликованных объявлений и время \
+            их публикации, чтобы объявления можно было редактировать. Эта информация не зашифрована, \
+            потому что она является публичной. Также хранится информация о звёздах. Эта информация \
+            хранится в зашифрованном (точнее, хешированном с секретной солью) виде. Список тех, кому вы \
+            вручили звёзды (/mystarred), хранится зашифрованным ключом, который выводится из вашего id \
+            и секрета сервера, поэтому из одной только базы его не восстановить.\n\n\
+            Где будут храниться данные форм, если я включу соответствующую настройку?\n\n\
             Эта информация будет храниться в вашем персональном облачном хранилище для этого бота от \
-            Telegram.".to_string()
+            Telegram и нужна только для предзаполнения новых объявлений.".to_string()
         }
     };
 
@@ -184,27 +676,294 @@ pub async fn handle_maintainer_command(
     command: MaintainerCommand,
 ) -> Result<(), RequestError> {
     use MaintainerCommand::*;
+
+    // per-group commands sent in a group are about it, in private about the group the maintainer chose
+    let requester = message.from.as_ref().unwrap().id;
+    let group_id = if config.is_registered(message.chat.id) {
+        message.chat.id
+    } else {
+        config.group_of(requester)
+    };
+
     match command {
         GetGroup => {
-            let msg = config.group_id().to_string();
+            let msg = config.groups().iter().enumerate()
+                .map(|(i, group)| if i == 0 { format!("{} (default)", group) } else { group.to_string() })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let msg = if msg.is_empty() { "No groups assigned".to_string() } else { msg };
+            bot.send_message(message.chat.id, msg).await.map(|_| ())
+        }
+        TestMsg => {
+            send_test_msg(bot, group_id, requester, config.callback_key()).await.map(|_| ())
+        }
+        Settings => {
+            let msg = format_settings(&config, group_id);
             bot.send_message(message.chat.id, msg).await.map(|_| ())
         }
+        Set(args) => {
+            let msg = set_setting(&config, group_id, &args);
+            bot.send_message(message.chat.id, msg).await.map(|_| ())
+        }
+        Reset(key) => {
+            let key = key.trim();
+            let msg = if config.default_settings.get(key).is_none() {
+                SettingError::UnknownKey.to_string()
+            } else {
+                match settings::reset(group_id, key, &config.redis_client) {
+                    Ok(true) => format!("{} is back to default: {}", key, config.default_settings.get(key).unwrap_or_default()),
+                    Ok(false) => format!("{} is not overridden", key),
+                    Err(e) => e.to_string(),
+                }
+            };
+            if key == "star_window" { drop_top_cache(group_id, &config.redis_client); }
+            bot.send_message(message.chat.id, msg).await.map(|_| ())
+        }
+        Grant(args) => {
+            let msg = match role_target(&message, &args) {
+                Some((user_id, role)) => match role.parse() {
+                    Ok(role) => assign_role(&config, requester, user_id, Some(role)),
+                    Err(e) => format!("role: {}", e),
+                },
+                None => "Usage: /grant <user_id> <role>, or /grant <role> in reply to the user".to_string(),
+            };
+            bot.send_message(message.chat.id, msg).await.map(|_| ())
+        }
+        Revoke(args) => {
+            let msg = match role_target(&message, &args) {
+                Some((user_id, _)) => assign_role(&config, requester, user_id, None),
+                None => "Usage: /revoke <user_id>, or /revoke in reply to the user".to_string(),
+            };
+            bot.send_message(message.chat.id, msg).await.map(|_| ())
+        }
+    }
+}
+
+pub async fn handle_moderator_command(
+    bot: Bot,
+    message: Message,
+    config: Arc<AppConfig>,
+    command: ModeratorCommand,
+) -> Result<(), RequestError> {
+    match command {
+        ModeratorCommand::Staff => {
+            let lines: Vec<_> = config.staff(Role::Moderator).into_iter()
+                .map(|(user_id, role)| format!("{} - {}", html::user_mention(user_id, &user_id.to_string()), role))
+                .collect();
+            bot.send_message(message.chat.id, lines.join("\n"))
+                .parse_mode(ParseMode::Html)
+                .await.map(|_| ())
+        }
+    }
+}
+
+pub async fn handle_owner_command(
+    bot: Bot,
+    message: Message,
+    config: Arc<AppConfig>,
+    command: OwnerCommand,
+) -> Result<(), RequestError> {
+    use OwnerCommand::*;
+
+    // migration sent in a group is to it, in private to the group the owner chose
+    let requester = message.from.as_ref().unwrap().id;
+    let group_id = if config.is_registered(message.chat.id) {
+        message.chat.id
+    } else {
+        config.group_of(requester)
+    };
+
+    match command {
         SetGroup(gid) => {
-            let res =
-                match config.redis_client.get_connection().unwrap()
-                    .set::<&str, i64, ()>(TARGET_GROUP_ID_KEY, gid)
-                {
-                    Ok(_) => String::from("Successfully set"),
-                    Err(e) => e.to_string()
-                };
+            let previous = config.group_id();
             config.set_group_id(gid);
+            let res = match groups::save(&config.groups(), &config.redis_client) {
+                Ok(_) => format!(
+                    "Successfully set. Stars of the previous group can be moved with /migrategroup {}",
+                    previous,
+                ),
+                Err(e) => e.to_string()
+            };
             bot.send_message(message.chat.id, res).await.map(|_| ())
         }
-        TestMsg => {
-            let gid = config.group_id();
-            send_test_msg(bot, gid, message.chat.id).await.map(|_| ())
+        AddGroup(gid) => {
+            let res = if !config.add_group(ChatId(gid)) {
+                String::from("The group is served already")
+            } else {
+                match groups::save(&config.groups(), &config.redis_client) {
+                    Ok(_) => String::from("Successfully added"),
+                    Err(e) => e.to_string()
+                }
+            };
+            bot.send_message(message.chat.id, res).await.map(|_| ())
+        }
+        RemoveGroup(gid) => {
+            let res = if !config.remove_group(ChatId(gid)) {
+                String::from("No such group")
+            } else {
+                match groups::save(&config.groups(), &config.redis_client) {
+                    Ok(_) => String::from("Successfully removed. Its data stays in the database"),
+                    Err(e) => e.to_string()
+                }
+            };
+            bot.send_message(message.chat.id, res).await.map(|_| ())
+        }
+        MigrateGroup(from) => {
+            use CallbackQueryCommand::MigrateGroup;
+
+            let to = group_id;
+            if from == to.0 {
+                return bot.send_message(message.chat.id, "This is the current group already").await.map(|_| ());
+            }
+
+            let client = config.redis_client.clone();
+            let res = tokio::task::spawn_blocking(move || migration::plan(ChatId(from), to, true, &client))
+                .await.expect("migration shouldn't panic");
+            let report = match res {
+                Ok(report) => report,
+                Err(e) => return bot.send_message(message.chat.id, e.to_string()).await.map(|_| ()),
+            };
+
+            let text = format!(
+                "Dry run of migration from {} to {}\n\n{}\n\nAds metadata only makes sense if messages \
+                of the old group are still there with the same ids.",
+                from, to, format_migration(&report, true),
+            );
+            let kb = make_signed_kb(vec![
+                vec![("Without ads".to_string(), MigrateGroup(from, false))],
+                vec![("With ads".to_string(), MigrateGroup(from, true))],
+            ], to, requester, config.callback_key());
+
+            bot.send_message(message.chat.id, text).reply_markup(kb).await.map(|_| ())
+        }
+        RekeyStars => {
+            let config = Arc::clone(&config);
+            let res = tokio::task::spawn_blocking(move || rekey_stars(&config))
+                .await.expect("rekeying shouldn't panic");
+
+            let res = match res {
+                Ok((report, index_entries)) => format!(
+                    "Re-keyed stars: {}\nUnrecognized stars: {}\nMoved starred index entries: {}",
+                    report.rekeyed, report.unrecognized, index_entries,
+                ),
+                Err(e) => e.to_string(),
+            };
+            bot.send_message(message.chat.id, res).await.map(|_| ())
+        }
+    }
+}
+
+/// User a role command is about: the one given by id, or the author of the replied message.
+/// Returns the rest of the arguments along with them.
+fn role_target<'a>(message: &Message, args: &'a str) -> Option<(UserId, &'a str)> {
+    let args = args.trim();
+    if let Some(user) = message.reply_to_message().and_then(|reply| reply.from.as_ref()) {
+        return Some((user.id, args));
+    }
+
+    let (user_id, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    Some((UserId(user_id.parse().ok()?), rest.trim()))
+}
+
+/// Changes the role of the user if the role of the requester allows it.
+fn assign_role(config: &AppConfig, requester: UserId, user_id: UserId, role: Option<Role>) -> String {
+    let Some(own) = config.role_of(requester) else { return "You have no role".to_string() };
+    let current = config.role_of(user_id);
+    if !own.can_assign(current, role) {
+        return format!("You can only grant and revoke roles below {}", own);
+    }
+    if current == role {
+        return "Nothing changed".to_string();
+    }
+
+    let res = match role {
+        Some(role) => roles::grant(user_id, role, &config.redis_client),
+        None => roles::revoke(user_id, &config.redis_client),
+    };
+    if let Err(e) = res {
+        return e.to_string();
+    }
+
+    config.set_role(user_id, role);
+    match role {
+        Some(role) => format!("{} is a {} now", user_id, role),
+        None => format!("{} has no role now", user_id),
+    }
+}
+
+/// Every setting of the group, marking those it overrides.
+fn format_settings(config: &AppConfig, group_id: ChatId) -> String {
+    let group_settings = config.settings_of(group_id);
+    let overrides = settings::load(group_id, &config.redis_client).unwrap_or_default();
+
+    let lines: Vec<_> = GroupSettings::KEYS.iter()
+        .map(|key| {
+            let value = group_settings.get(key).unwrap_or_default();
+            let value = if value.is_empty() { "-".to_string() } else { value };
+            let mark = if overrides.contains_key(*key) { "" } else { " (default)" };
+            format!("{} = {}{}", key, value, mark)
+        })
+        .collect();
+
+    format!("Settings of {}\n\n{}\n\nChange with /set <key> <value>, undo with /reset <key>", group_id, lines.join("\n"))
+}
+
+/// Validates and stores `<key> <value>` given to /set.
+fn set_setting(config: &AppConfig, group_id: ChatId, args: &str) -> String {
+    let Some((key, value)) = args.trim().split_once(char::is_whitespace) else {
+        return "Usage: /set <key> <value>".to_string();
+    };
+
+    let mut group_settings = config.settings_of(group_id);
+    if let Err(e) = group_settings.set(key, value) {
+        return format!("{}: {}", key, e);
+    }
+    if let Err(e) = settings::save(group_id, key, value.trim(), &config.redis_client) {
+        return e.to_string();
+    }
+
+    // the leaderboard counts stars within the window
+    if key == "star_window" { drop_top_cache(group_id, &config.redis_client); }
+    format!("{} = {}", key, group_settings.get(key).unwrap_or_default())
+}
+
+fn drop_top_cache(group_id: ChatId, client: &redis::Client) {
+    if let Err(e) = crate::store::top::invalidate(group_id, client) {
+        log::error!("failed to invalidate leaderboard: {}", e.to_string());
+    }
+}
+
+fn format_migration(report: &MigrationReport, with_ads: bool) -> String {
+    let mut text = format!(
+        "Users with stars: {}\nStars: {} ({} new)\nStarred indexes: {}\n\
+        Complaints: {} ({} new)\nUsers with warnings: {}\nDeals: {} ({} new)",
+        report.starred_users, report.stars, report.new_stars, report.starred_indexes,
+        report.complaints, report.new_complaints, report.warned_users, report.deals, report.new_deals,
+    );
+    if with_ads {
+        text += &format!("\nAds: {} ({} new)", report.ads, report.new_ads);
+    }
+    text
+}
+
+/// Moves stars and giver-side indexes of every known user of every group to the current star salt.
+fn rekey_stars(config: &AppConfig) -> RedisResult<(RekeyReport, usize)> {
+    let client = &config.redis_client;
+
+    let mut report = RekeyReport::default();
+    let mut index_entries = 0;
+    for group_id in config.groups() {
+        let group_report = store::rekey_stars(group_id, &config.star_salts, client)?;
+        report.rekeyed += group_report.rekeyed;
+        report.unrecognized += group_report.unrecognized;
+
+        for giver in store::known_users(group_id, client)? {
+            index_entries += starred::rekey(group_id, giver, &config.star_salts, client)?;
         }
     }
+    store::mark_stars_rekeyed(client)?;
+
+    Ok((report, index_entries))
 }
 
 pub async fn handle_shared_users(
@@ -212,55 +971,718 @@ pub async fn handle_shared_users(
     config: Arc<AppConfig>,
     message: Message,
 ) -> Result<(), RequestError> {
-    let group_id = config.group_id();
-
     let giver = message.from.unwrap();
     let giver_id = giver.id;
+    let group_id = config.group_of(giver_id);
+
+    let users = match message.kind {
+        MessageKind::UsersShared(users) => users.users_shared,
+        _ => return Ok(()),
+    };
+    // those who left the group can still take back stars they gave
+    if users.request_id == TAKE_STAR_REQUEST_ID {
+        return take_stars(bot, config, group_id, giver_id, users.user_ids).await;
+    }
 
     // silently ignore non-members
     if ! &config.bot.get_chat_member(group_id, giver_id).await?.is_present() {
         return Ok(());
     }
 
-    let mut count = 0;
-    match message.kind {
-        MessageKind::UsersShared(mut users) => {
-            while let Some(receiver_id) = users.users_shared.user_ids.pop() {
-                // can't give stars to yourself
-                if receiver_id == giver_id { continue; }
-
-                // if receiver is group not member, don't give them star
-                if let Some(member) = &config.bot.get_chat_member(group_id, receiver_id).await.ok() {
-                    if ! member.is_present() {
-                        continue;
-                    }
-                } else {
+    if users.request_id == COMPLAINT_REQUEST_ID {
+        return start_complaint(bot, config, group_id, giver_id, users.user_ids).await;
+    }
+    if users.request_id == DEAL_REQUEST_ID {
+        return propose_deal(bot, config, group_id, giver, users.user_ids).await;
+    }
+
+    let client = &config.redis_client;
+    let day = ads::now() / DAY_SECS;
+    let settings = config.settings_of(group_id);
+    let rules = &settings.star_rules;
+
+    let now = ads::now();
+    let giver_check = members::joined_at(group_id, giver_id, now, client)
+        .and_then(|joined_at| {
+            let stars = get_star_count(giver_id, group_id, settings.star_window, client)?;
+            Ok(rules.check_giver(now.saturating_sub(joined_at), stars))
+        });
+    match giver_check {
+        Ok(Ok(())) => {} // continue
+        Ok(Err(refusal)) => {
+            return bot.send_message(giver_id, format!("Звёзды не вручены: {}", refusal)).await.map(|_| ());
+        }
+        Err(e) => {
+            log::error!("failed to check star rules: {}", e.to_string());
+            return bot.send_message(giver_id, "Что-то пошло не так, попробуйте позднее").await.map(|_| ());
+        }
+    }
+
+    let mut new_star_receivers = vec![];
+    let mut renewed = vec![];
+    let mut refused = vec![];
+    let mut user_ids = users.user_ids;
+    while let Some(receiver_id) = user_ids.pop() {
+        let member = config.bot.get_chat_member(group_id, receiver_id).await.ok();
+        let name = member.as_ref().map(|member| member.user.full_name()).unwrap_or_else(|| "пользователь".to_string());
+
+        // can't give stars to yourself
+        if receiver_id == giver_id {
+            refused.push((receiver_id, name, StarRefusal::Yourself));
+            continue;
+        }
+
+        // if receiver is group not member, don't give them star
+        let receiver = match member {
+            Some(member) if member.is_present() => member.user,
+            _ => {
+                refused.push((receiver_id, name, StarRefusal::NotMember));
+                continue;
+            }
+        };
+
+        // renewing a star doesn't take new trust, so it's outside of the budget
+        let starred_user = StarredUser { id: receiver_id, name: receiver.full_name() };
+        let has_star = starred::contains(group_id, giver_id, receiver_id, config.star_salt(), client)
+            .unwrap_or_default();
+        if !has_star {
+            match members::reserve_star(group_id, giver_id, day, |given| rules.within_budget(given), client) {
+                Ok(true) => {} // continue
+                Ok(false) => {
+                    refused.push((receiver_id, name, StarRefusal::BudgetSpent { budget: rules.daily_budget.unwrap_or_default() }));
                     continue;
                 }
+                Err(e) => {
+                    log::error!("failed to count given star: {}", e.to_string());
+                    return bot.send_message(giver_id, "Что-то пошло не так, попробуйте позднее").await.map(|_| ());
+                }
+            }
+        }
+
+        let is_new = match give_star(giver_id, receiver_id, group_id, &config.star_salts, client) {
+            Ok(is_new) => is_new,
+            Err(e) => {
+                log::error!("failed to give star: {}", e.to_string());
+                if !has_star {
+                    if let Err(e) = members::release_star(group_id, giver_id, day, client) {
+                        log::error!("failed to release star: {}", e.to_string());
+                    }
+                }
+                refused.push((receiver_id, name, StarRefusal::Failed));
+                continue;
+            }
+        };
+
+        // stars given before the index existed get there on the second try
+        if let Err(e) = starred::add(group_id, giver_id, &starred_user, config.star_salt(), client) {
+            log::error!("failed to index star: {}", e.to_string());
+        }
+
+        // the index can be behind the stars, then the count is fixed up
+        let counted = match (is_new, has_star) {
+            (true, true) => members::reserve_star(group_id, giver_id, day, |_| true, client).map(|_| ()),
+            (false, false) => members::release_star(group_id, giver_id, day, client),
+            _ => Ok(()),
+        };
+        if let Err(e) = counted {
+            log::error!("failed to count given star: {}", e.to_string());
+        }
+
+        if is_new {
+            new_star_receivers.push(receiver);
+        } else {
+            renewed.push((receiver_id, name));
+        }
+    }
+
+    let mut text = format!("Успешно врученных звёзд: {}", new_star_receivers.len());
+    if !renewed.is_empty() {
+        let lines: Vec<_> = renewed.iter()
+            .map(|(id, name)| html::user_mention(*id, &html::escape(name)))
+            .collect();
+        text += &format!("\n\nПродлены ваши звёзды:\n{}", lines.join("\n"));
+    }
+    if !refused.is_empty() {
+        let lines: Vec<_> = refused.iter()
+            .map(|(id, name, refusal)| format!("{} - {}", html::user_mention(*id, &html::escape(name)), refusal))
+            .collect();
+        text += &format!("\n\nНе вручены:\n{}", lines.join("\n"));
+    }
+
+    for receiver in new_star_receivers {
+        notify_star_receiver(&bot, &config, group_id, receiver.id).await;
+        refresh_badges(Arc::clone(&config), group_id, receiver);
+    }
+
+    bot.send_message(giver_id, text)
+        .parse_mode(ParseMode::Html)
+        .await.map(|_|())
+}
+
+/// Remembers when members join the group, so star rules can tell fresh accounts.
+pub async fn handle_chat_member(
+    config: Arc<AppConfig>,
+    update: ChatMemberUpdated,
+) -> Result<(), RequestError> {
+    let group_id = update.chat.id;
+    if !config.is_registered(group_id) { return Ok(()) }
+
+    let user_id = update.new_chat_member.user.id;
+    let client = &config.redis_client;
+    let res = match (update.old_chat_member.is_present(), update.new_chat_member.is_present()) {
+        (false, true) => members::set_joined(group_id, user_id, update.date.timestamp() as u64, client),
+        (true, false) => members::forget_joined(group_id, user_id, client),
+        _ => Ok(()),
+    };
+
+    if let Err(e) = res {
+        log::error!("failed to track member: {}", e.to_string());
+    }
+    Ok(())
+}
+
+/// Lets the receiver know about a new star, without telling who gave it.
+async fn notify_star_receiver(bot: &Bot, config: &AppConfig, group_id: ChatId, receiver_id: UserId) {
+    let client = &config.redis_client;
+
+    match star_notifications_enabled(receiver_id, group_id, client) {
+        Ok(true) => {} // continue
+        Ok(false) => return,
+        Err(e) => {
+            log::error!("failed to check star notifications: {}", e.to_string());
+            return;
+        }
+    }
+
+    let star_window = config.settings_of(group_id).star_window;
+    let count = match get_star_count(receiver_id, group_id, star_window, client) {
+        Ok(count) => count,
+        Err(e) => {
+            log::error!("failed to get star count: {}", e.to_string());
+            return;
+        }
+    };
+
+    let text = format!(
+        "Вам вручили звезду, теперь у вас {}⭐\n\nОтключить эти уведомления: /starnotify",
+        count,
+    );
+    match bot.send_message(receiver_id, text).await {
+        Ok(_) => {}
+        // receivers who never started the bot or blocked it can't be messaged
+        Err(RequestError::Api(ApiError::BotBlocked | ApiError::CantInitiateConversation
+            | ApiError::UserDeactivated)) => {}
+        Err(e) => log::error!("failed to notify star receiver: {}", e.to_string()),
+    }
+}
+
+/// Asks the user for the reason of the complaint about the shared user.
+async fn start_complaint(
+    bot: Bot,
+    config: Arc<AppConfig>,
+    group_id: ChatId,
+    complainer_id: UserId,
+    target_ids: Vec<UserId>,
+) -> Result<(), RequestError> {
+    let Some(&target_id) = target_ids.first() else { return Ok(()) };
+    if target_id == complainer_id {
+        return bot.send_message(complainer_id, "Нельзя пожаловаться на себя").await.map(|_| ());
+    }
+
+    let text = match complaints::start(
+        group_id,
+        complainer_id,
+        target_id,
+        COMPLAINT_REASON_TIMEOUT_SECS,
+        &config.redis_client,
+    ) {
+        Ok(_) => "Опишите одним сообщением, что произошло. Жалобу увидят только модераторы, \
+            и только они решат, отмечать ли пользователя предупреждением.",
+        Err(e) => {
+            log::error!("failed to start complaint: {}", e.to_string());
+            "Что-то пошло не так, попробуйте позднее"
+        }
+    };
+
+    bot.send_message(complainer_id, text).await.map(|_| ())
+}
+
+/// Takes the reason of a started complaint and hands the complaint over to moderators.
+/// Other private messages are ignored.
+pub async fn handle_complaint_reason(
+    bot: Bot,
+    config: Arc<AppConfig>,
+    message: Message,
+) -> Result<(), RequestError> {
+    let reason = message.text().unwrap_or_default().to_string();
+    let complainer = message.from.unwrap();
+    let group_id = config.group_of(complainer.id);
+
+    let complaint = match complaints::file(group_id, complainer.id, reason, &config.redis_client) {
+        Ok(Some(complaint)) => complaint,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            log::error!("failed to file complaint: {}", e.to_string());
+            return bot.send_message(complainer.id, "Что-то пошло не так, попробуйте позднее")
+                .await.map(|_| ());
+        }
+    };
+
+    let target = match config.bot.get_chat_member(group_id, complaint.target).await {
+        Ok(member) => html::user_mention(member.user.id, &html::escape(&member.user.full_name())),
+        Err(_) => html::user_mention(complaint.target, "пользователь"),
+    };
+    let text = format!(
+        "Жалоба #{}\nОт: {}\nНа: {}\n\n{}",
+        complaint.id,
+        html::user_mention(complainer.id, &html::escape(&complainer.full_name())),
+        target,
+        html::escape(&complaint.reason),
+    );
+
+    // buttons are signed for each moderator, the first one to press them settles the complaint
+    for (moderator, _) in config.staff(Role::Moderator) {
+        let kb = make_signed_kb(vec![vec![
+            ("Одобрить ⚠️".to_string(), CallbackQueryCommand::ApproveComplaint(complaint.id)),
+            ("Отклонить ❌".to_string(), CallbackQueryCommand::RejectComplaint(complaint.id)),
+        ]], group_id, moderator, config.callback_key());
+
+        let res = bot.send_message(moderator, &text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(kb)
+            .await;
+        if let Err(e) = res {
+            log::warn!("failed to send complaint to {}: {}", moderator, e.to_string());
+        }
+    }
+
+    bot.send_message(complainer.id, "Жалоба отправлена модераторам").await.map(|_| ())
+}
+
+/// Settles the complaint and lets the complainer know. Returns the answer for the moderator.
+async fn resolve_complaint(
+    bot: &Bot,
+    config: &Arc<AppConfig>,
+    group_id: ChatId,
+    id: u64,
+    approve: bool,
+) -> &'static str {
+    let client = &config.redis_client;
+
+    let complaint = match complaints::resolve(group_id, id, approve, client) {
+        Ok(Some(complaint)) => complaint,
+        Ok(None) => return "Жалоба уже рассмотрена",
+        Err(e) => {
+            log::error!("failed to resolve complaint: {}", e.to_string());
+            return "Something went wrong";
+        }
+    };
+
+    let text = if approve {
+        "Ваша жалоба одобрена, пользователь отмечен предупреждением"
+    } else {
+        "Ваша жалоба отклонена модераторами"
+    };
+    if let Err(e) = bot.send_message(complaint.complainer, text).await {
+        log::warn!("failed to notify complainer: {}", e.to_string());
+    }
+
+    if approve {
+        if let Ok(member) = config.bot.get_chat_member(group_id, complaint.target).await {
+            refresh_badges(Arc::clone(config), group_id, member.user);
+        }
+        "Жалоба одобрена"
+    } else {
+        "Жалоба отклонена"
+    }
+}
+
+/// Asks the shared user to confirm they had a deal with the proposer.
+async fn propose_deal(
+    bot: Bot,
+    config: Arc<AppConfig>,
+    group_id: ChatId,
+    proposer: User,
+    counterparty_ids: Vec<UserId>,
+) -> Result<(), RequestError> {
+    let Some(&counterparty_id) = counterparty_ids.first() else { return Ok(()) };
+    if counterparty_id == proposer.id {
+        return bot.send_message(proposer.id, "Сделку с самим собой не засчитать").await.map(|_| ());
+    }
+
+    // both parties have to be members
+    match config.bot.get_chat_member(group_id, counterparty_id).await {
+        Ok(member) if member.is_present() => {}
+        _ => {
+            return bot.send_message(proposer.id, "Этот пользователь не состоит в группе")
+                .await.map(|_| ());
+        }
+    }
+
+    let proposal = deals::propose(
+        group_id,
+        proposer.id,
+        counterparty_id,
+        MAX_DEAL_PROPOSALS_PER_DAY,
+        DAY_SECS,
+        &config.redis_client,
+    );
+    let deal = match proposal {
+        Ok(deal) => deal,
+        Err(ProposalError::Confirmed) => {
+            return bot.send_message(proposer.id, "Сделка с этим пользователем уже засчитана").await.map(|_| ());
+        }
+        Err(ProposalError::TooMany) => {
+            return bot.send_message(proposer.id, "Слишком много предложений сделок за сутки, попробуйте завтра")
+                .await.map(|_| ());
+        }
+        Err(ProposalError::Redis(e)) => {
+            log::error!("failed to propose deal: {}", e.to_string());
+            return bot.send_message(proposer.id, "Что-то пошло не так, попробуйте позднее")
+                .await.map(|_| ());
+        }
+    };
+
+    let text = format!(
+        "{} отмечает, что между вами состоялась сделка. Подтвердите, если это так - \
+        подтверждённые сделки показываются рядом со звёздами в объявлениях у вас обоих.",
+        html::user_mention(proposer.id, &html::escape(&proposer.full_name())),
+    );
+    let kb = make_signed_kb(vec![vec![
+        ("Подтвердить 🤝".to_string(), CallbackQueryCommand::ConfirmDeal(deal.id)),
+        ("Не было ❌".to_string(), CallbackQueryCommand::DeclineDeal(deal.id)),
+    ]], group_id, counterparty_id, config.callback_key());
+
+    let res = bot.send_message(counterparty_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(kb)
+        .await;
+
+    let text = match res {
+        Ok(_) => "Предложение отправлено, сделка засчитается после подтверждения",
+        Err(RequestError::Api(ApiError::BotBlocked | ApiError::CantInitiateConversation)) => {
+            "Бот не может написать этому пользователю. Попросите этого пользователя запустить бота и попробуйте снова"
+        }
+        Err(e) => return Err(e),
+    };
+
+    bot.send_message(proposer.id, text).await.map(|_| ())
+}
+
+/// Settles the proposed deal and lets the proposer know. Returns the answer for the counterparty.
+async fn resolve_deal(
+    bot: &Bot,
+    config: &Arc<AppConfig>,
+    group_id: ChatId,
+    id: u64,
+    confirm: bool,
+) -> &'static str {
+    let client = &config.redis_client;
+
+    let deal = match deals::resolve(group_id, id, confirm, client) {
+        Ok(Some(deal)) => deal,
+        Ok(None) => return "Сделка уже рассмотрена",
+        Err(e) => {
+            log::error!("failed to resolve deal: {}", e.to_string());
+            return "Something went wrong";
+        }
+    };
+
+    let text = if confirm { "Сделка подтверждена 🤝" } else { "Сделка не подтверждена" };
+    if let Err(e) = bot.send_message(deal.proposer, text).await {
+        log::warn!("failed to notify deal proposer: {}", e.to_string());
+    }
+
+    if confirm {
+        for user_id in [deal.proposer, deal.counterparty] {
+            if let Ok(member) = config.bot.get_chat_member(group_id, user_id).await {
+                refresh_badges(Arc::clone(config), group_id, member.user);
+            }
+        }
+    }
+
+    text
+}
+
+/// Takes back stars the user gave to the shared users earlier.
+async fn take_stars(
+    bot: Bot,
+    config: Arc<AppConfig>,
+    group_id: ChatId,
+    giver_id: UserId,
+    receiver_ids: Vec<UserId>,
+) -> Result<(), RequestError> {
+
+    let mut count = 0;
+    for receiver_id in receiver_ids {
+        let taken = take_star(
+            giver_id,
+            receiver_id,
+            &config.star_salts,
+            &store::stars_key(group_id, receiver_id),
+            &config.redis_client,
+        );
+        if let Err(e) = starred::remove(group_id, giver_id, receiver_id, config.star_salt(), &config.redis_client) {
+            log::error!("failed to unindex star: {}", e.to_string());
+        }
+
+        match taken {
+            Ok(true) => count += 1,
+            Ok(false) => continue,
+            Err(e) => {
+                log::error!("failed to take star: {}", e.to_string());
+                continue;
+            }
+        }
+
+        // ads of those who left the group are gone anyway
+        if let Ok(member) = config.bot.get_chat_member(group_id, receiver_id).await {
+            if member.is_present() {
+                refresh_badges(Arc::clone(&config), group_id, member.user);
+            }
+        }
+    }
+
+    bot.send_message(giver_id, format!("Забрано звёзд: {}", count)).await.map(|_|())
+}
+
+/// Re-renders ads of a user whose star count has changed, in the background.
+/// If it was done recently, the user is queued for the scheduler instead.
+fn refresh_badges(config: Arc<AppConfig>, group_id: ChatId, user: User) {
+    match badges::try_refresh(group_id, user.id, BADGE_REFRESH_INTERVAL_SECS, &config.redis_client) {
+        Ok(true) => {
+            tokio::spawn(async move {
+                let mut sw_user = user.in_group(&config, group_id).await;
+                refresh_ads(&config, &mut sw_user).await;
+            });
+        }
+        Ok(false) => {} // queued
+        Err(e) => log::error!("failed to throttle ads refresh: {}", e.to_string()),
+    }
+}
+
+/// Text and navigation buttons of a leaderboard page, for the user who asked for it.
+async fn render_top_page(
+    config: &AppConfig,
+    group_id: ChatId,
+    page: usize,
+    user_id: UserId,
+) -> (String, InlineKeyboardMarkup) {
+    use CallbackQueryCommand::TopPage;
+
+    // names and links of members are for members only
+    match config.bot.get_chat_member(group_id, user_id).await {
+        Ok(member) if member.is_present() => {}
+        Ok(_) => return ("Рейтинг доступен только участникам группы".to_string(), InlineKeyboardMarkup::default()),
+        Err(e) => {
+            log::error!("member check failed: {}", e.to_string());
+            return ("Что-то пошло не так, попробуйте позднее".to_string(), InlineKeyboardMarkup::default());
+        }
+    }
+
+    let leaderboard = match top::leaderboard(config, group_id) {
+        Ok(Some(leaderboard)) => leaderboard,
+        Ok(None) => return ("Рейтинг ещё составляется, загляните через пару минут".to_string(), InlineKeyboardMarkup::default()),
+        Err(e) => {
+            log::error!("failed to load leaderboard: {}", e.to_string());
+            return ("Что-то пошло не так, попробуйте позднее".to_string(), InlineKeyboardMarkup::default());
+        }
+    };
+
+    let pages = top::page_count(&leaderboard);
+    let page = page.min(pages - 1);
+    let lines: Vec<_> = top::page(&leaderboard, page).iter().enumerate()
+        .map(|(i, entry)| format!("{}. {} {}⭐", page * TOP_PAGE_SIZE + i + 1, entry.link, entry.stars))
+        .collect();
+
+    let text = if lines.is_empty() {
+        "Пока ни у кого нет звёзд".to_string()
+    } else {
+        format!("Больше всего звёзд у:\n\n{}\n\nСкрыть себя из списка: /tophide", lines.join("\n"))
+    };
+
+    let mut row = vec![];
+    if page > 0 { row.push(("◀️".to_string(), TopPage(page - 1))); }
+    if page + 1 < pages { row.push(("▶️".to_string(), TopPage(page + 1))); }
+
+    (text, make_signed_kb(vec![row], group_id, user_id, config.callback_key()))
+}
+
+/// Offers the user to choose among registered groups they are a member of.
+async fn send_groups(bot: &Bot, config: &AppConfig, current: ChatId, msg: Message) -> Result<(), RequestError> {
+    use CallbackQueryCommand::SelectGroup;
 
-                give_star(
-                    giver_id,
-                    receiver_id,
-                    config.bot_token.as_bytes(),
-                    &format!("{}:{}:stars", group_id, receiver_id.0),
-                    &config.redis_client,
-                ).unwrap();
-                count += 1;
+    let user_id = msg.from.unwrap().id;
+    let mut butts = vec![];
+    for group_id in config.groups() {
+        match bot.get_chat_member(group_id, user_id).await {
+            Ok(member) if member.is_present() => {}
+            _ => continue,
+        }
+        let title = bot.get_chat(group_id).await.ok()
+            .and_then(|chat| chat.title().map(str::to_string))
+            .unwrap_or_else(|| group_id.to_string());
+        let title = if group_id == current { format!("{} ✅", title) } else { title };
+        // every button is signed for its own group
+        let data = SelectGroup(group_id.0).sign(group_id, user_id, config.callback_key());
+        butts.push(vec![InlineKeyboardButton::callback(title, data)]);
+    }
+
+    if butts.len() < 2 {
+        return bot.send_message(msg.chat.id, "Выбирать не из чего: вы состоите только в одной из групп бота")
+            .await.map(|_| ());
+    }
+
+    bot.send_message(msg.chat.id, "Выберите группу, с которой будете работать в этом чате: \
+        объявления, звёзды, сделки и жалобы относятся к ней.")
+        .reply_markup(InlineKeyboardMarkup::new(butts))
+        .await.map(|_| ())
+}
+
+/// Lists users the giver starred, as far as the giver-side index knows.
+async fn send_my_starred(bot: &Bot, config: &AppConfig, group_id: ChatId, msg: Message) -> Result<(), RequestError> {
+    let giver_id = msg.from.unwrap().id;
+    // the index could have been made before the star salt changed
+    if let Err(e) = starred::rekey(group_id, giver_id, &config.star_salts, &config.redis_client) {
+        log::error!("failed to rekey starred index: {}", e.to_string());
+    }
+    let starred = starred::list(group_id, giver_id, config.star_salt(), &config.redis_client);
+
+    let text = match starred {
+        Ok(starred) if starred.is_empty() => "Вы пока никому не вручали звёзд. Звёзды, врученные \
+            до появления этой команды, здесь не видны, но их можно вручить повторно - второй раз \
+            звезда не засчитается, а человек появится в списке.".to_string(),
+        Ok(starred) => {
+            let lines: Vec<_> = starred.iter()
+                .map(|user| format!("⭐️ {}", html::user_mention(user.id, &html::escape(&user.name))))
+                .collect();
+            format!("Вы вручили звёзды:\n{}", lines.join("\n"))
+        }
+        Err(e) => {
+            log::error!("failed to list starred users: {}", e.to_string());
+            "Что-то пошло не так, попробуйте позднее".to_string()
+        }
+    };
+
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
+        .await.map(|_| ())
+}
+
+/// Sends a copy of every active ad of the user with management buttons.
+/// Ads that are gone from the group are forgotten along the way.
+async fn send_my_ads(bot: &Bot, config: &AppConfig, group_id: ChatId, msg: Message) -> Result<(), RequestError> {
+    let chat_id = msg.chat.id;
+    let mut sw_user = msg.from.unwrap().in_group(config, group_id).await;
+
+    let ids = match sw_user.ads().await {
+        Ok(ids) => ids,
+        Err(e) => {
+            log::error!("failed to get ads: {}", e.to_string());
+            return bot.send_message(chat_id, "Что-то пошло не так, попробуйте позднее")
+                .await.map(|_| ());
+        }
+    };
+
+    let mut shown = 0;
+    for msg_id in ids {
+        let stored = ads::load(group_id, msg_id, &config.redis_client).unwrap_or_else(|e| {
+            log::error!("failed to load ad: {}", e.to_string());
+            None
+        });
+        // only stored ads can be edited, the editor is prefilled from the record
+        let kb = make_report_kb(config, group_id, msg_id, sw_user.tg_user.id, stored.is_some());
+
+        match bot.copy_message(chat_id, group_id, msg_id).reply_markup(kb).await {
+            Ok(copy_id) => {
+                shown += 1;
+                // the copy is the freshest place to manage the ad from
+                if let Some(mut ad) = stored {
+                    ad.report_msg_id = Some(copy_id);
+                    if let Err(e) = ads::save(group_id, &ad, &config.redis_client) {
+                        log::error!("failed to save ad: {}", e.to_string());
+                    }
+                }
             }
+            Err(RequestError::Api(ApiError::MessageToCopyNotFound)) => {
+                if let Err(e) = ads::forget(group_id, sw_user.tg_user.id, msg_id, &config.redis_client) {
+                    log::error!("failed to forget ad: {}", e.to_string());
+                }
+            }
+            Err(e) => log::error!("failed to copy ad {}: {}", msg_id, e.to_string()),
         }
-        _ => ()
     }
 
-    bot.send_message(giver_id, format!("Успешно врученных звёзд: {}", count)).await.map(|_|())
+    if shown == 0 {
+        bot.send_message(chat_id, "У вас нет активных объявлений").await?;
+    }
+
+    Ok(())
 }
 
-async fn send_test_msg(bot: Bot, dst_chat_id: ChatId, requester_chat_id: ChatId) -> Result<(), RequestError> {
+/// Takes the user's ad out of the publication queue.
+/// Answers the callback query if that's not possible, so the caller only has to return.
+async fn take_scheduled(
+    bot: &Bot,
+    callback_query: &CallbackQuery,
+    config: &AppConfig,
+    group_id: ChatId,
+    id: u64,
+) -> Result<Option<ScheduledAd>, RequestError> {
+    let client = &config.redis_client;
+
+    let res = scheduled::load(group_id, id, client).and_then(|ad| match ad {
+        // somebody else could have taken it while we were looking
+        Some(ad) => scheduled::remove(group_id, &ad, client).map(|removed| removed.then_some(ad)),
+        None => Ok(None),
+    });
+
+    let text = match res {
+        Ok(Some(ad)) => return Ok(Some(ad)),
+        Ok(None) => "Это объявление уже не ждёт публикации",
+        Err(e) => {
+            log::error!("failed to take scheduled ad: {}", e.to_string());
+            "Something went wrong"
+        }
+    };
+
+    bot.answer_callback_query(callback_query.id.clone())
+        .text(text)
+        .await.map(|_| None)
+}
+
+async fn move_authorship(
+    sw_user: &mut SwappyUser<'_>,
+    old_id: MessageId,
+    new_id: MessageId,
+) -> RedisResult<()> {
+    sw_user.unset_author(old_id).await?;
+    sw_user.set_author(new_id).await?;
+    sw_user.set_repost_cooldown(new_id).await
+}
+
+pub fn format_duration(secs: u64) -> String {
+    let (hours, mins) = (secs / 3600, secs % 3600 / 60);
+    match (hours, mins) {
+        (0, 0) => "минуту".to_string(),
+        (0, m) => format!("{m} мин."),
+        (h, 0) => format!("{h} ч."),
+        (h, m) => format!("{h} ч. {m} мин."),
+    }
+}
+
+async fn send_test_msg(bot: Bot, dst_chat_id: ChatId, requester: UserId, key: &[u8]) -> Result<(), RequestError> {
     let sent_msg = bot.send_message(dst_chat_id, "Hi, this is a test message").await?;
 
     let report = format!("sent\nid: `{}`\ngroup_id: `{}`", sent_msg.id, dst_chat_id);
-    bot.send_message(requester_chat_id, report)
+    bot.send_message(requester, report)
         // .parse_mode(ParseMode::MarkdownV2)
-        .reply_markup(make_kb(vec![("Delete".into(), sent_msg.id.to_string())]))
+        .reply_markup(make_signed_kb(
+            vec![vec![("Delete".into(), CallbackQueryCommand::Delete(sent_msg.id))]],
+            dst_chat_id,
+            requester,
+            key,
+        ))
         .await.map(|_| ())
 }
 
@@ -276,6 +1698,18 @@ pub fn make_callback_kb(butts: Vec<Vec<(String, impl Display)>>) -> InlineKeyboa
     InlineKeyboardMarkup::new(kb)
 }
 
+/// Same as [`make_callback_kb`], with callback data about the group signed for the user who gets the keyboard.
+pub fn make_signed_kb(
+    butts: Vec<Vec<(String, CallbackQueryCommand)>>,
+    group_id: ChatId,
+    user_id: UserId,
+    key: &[u8],
+) -> InlineKeyboardMarkup {
+    make_callback_kb(butts.into_iter().map(|row| {
+        row.into_iter().map(|(text, cmd)| (text, cmd.sign(group_id, user_id, key))).collect()
+    }).collect())
+}
+
 pub fn make_kb(butts: Vec<(String, String)>) -> InlineKeyboardMarkup
 {
     let mut kb: Vec<Vec<InlineKeyboardButton>> = vec![];
@@ -292,16 +1726,46 @@ pub fn make_kb(butts: Vec<(String, String)>) -> InlineKeyboardMarkup
 }
 
 fn make_start_kb() -> KeyboardMarkup {
-    let mut kb: Vec<Vec<KeyboardButton>> = vec![];
-
-    kb.push(vec![
+    let kb: Vec<Vec<KeyboardButton>> = vec![vec![
         KeyboardButton::new("Вручить ⭐️").request(RequestUsers(KeyboardButtonRequestUsers {
-            request_id: RequestId(1),
+            request_id: GIVE_STAR_REQUEST_ID,
             user_is_bot: Some(false),
             user_is_premium: None,
             max_quantity: 10,
-        }))
-    ]);
+        })),
+        KeyboardButton::new("Забрать ⭐️").request(RequestUsers(KeyboardButtonRequestUsers {
+            request_id: TAKE_STAR_REQUEST_ID,
+            user_is_bot: Some(false),
+            user_is_premium: None,
+            max_quantity: 10,
+        })),
+    ], vec![
+        KeyboardButton::new("Сделка 🤝").request(RequestUsers(KeyboardButtonRequestUsers {
+            request_id: DEAL_REQUEST_ID,
+            user_is_bot: Some(false),
+            user_is_premium: None,
+            max_quantity: 1,
+        })),
+        KeyboardButton::new("Пожаловаться ⚠️").request(RequestUsers(KeyboardButtonRequestUsers {
+            request_id: COMPLAINT_REQUEST_ID,
+            user_is_bot: Some(false),
+            user_is_premium: None,
+            max_quantity: 1,
+        })),
+    ]];
 
     KeyboardMarkup::new(kb).resize_keyboard()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_duration;
+
+    #[test]
+    fn duration_is_formatted() {
+        assert_eq!(format_duration(30), "минуту");
+        assert_eq!(format_duration(25 * 60), "25 мин.");
+        assert_eq!(format_duration(2 * 3600), "2 ч.");
+        assert_eq!(format_duration(11 * 3600 + 59 * 60 + 59), "11 ч. 59 мин.");
+    }
+}
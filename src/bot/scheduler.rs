@@ -0,0 +1,228 @@
+use std::sync::Arc;
+use std::time::Duration;
+use redis::RedisResult;
+use teloxide::payloads::SendMessageSetters;
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, ParseMode};
+use crate::bot::commands::CallbackQueryCommand::Renew;
+use crate::bot::{make_signed_kb, top};
+use crate::bot::BADGE_REFRESH_INTERVAL_SECS;
+use crate::site::{publish_scheduled, refresh_ads};
+use crate::store::ads::{self, AdStatus};
+use crate::store::{badges, scheduled};
+use crate::store::scheduled::ScheduledAd;
+use crate::types::{AppConfig, ToSwappyUser};
+
+const TICK: Duration = Duration::from_secs(60);
+
+/// Scheduled ads that fail to get published are tried again this many times in total.
+const MAX_PUBLISH_ATTEMPTS: u32 = 3;
+const PUBLISH_RETRY_SECS: u64 = 10 * 60;
+/// Reason a scheduled ad of someone who left the group is cancelled with.
+pub const NOT_A_MEMBER: &str = "Запланированное объявление отменено: вы больше не состоите в группе.";
+
+/// Runs periodic jobs. Meant to be spawned next to the dispatcher.
+pub async fn run(config: Arc<AppConfig>) {
+    // takes a request per ranked member, so it doesn't hold up the other jobs
+    tokio::spawn(rebuild_leaderboards(Arc::clone(&config)));
+
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+
+        for group_id in config.groups() {
+            if let Err(e) = expire_ads(&config, group_id).await {
+                log::error!("failed to expire ads in {}: {}", group_id, e.to_string());
+            }
+
+            if let Err(e) = publish_scheduled_ads(&config, group_id).await {
+                log::error!("failed to publish scheduled ads in {}: {}", group_id, e.to_string());
+            }
+
+            if let Err(e) = refresh_pending_badges(&config, group_id).await {
+                log::error!("failed to refresh badges in {}: {}", group_id, e.to_string());
+            }
+        }
+    }
+}
+
+/// Keeps leaderboards of the groups no older than [`TOP_CACHE_SECS`](crate::bot::TOP_CACHE_SECS).
+async fn rebuild_leaderboards(config: Arc<AppConfig>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+
+        for group_id in config.groups() {
+            if let Err(e) = top::rebuild(&config, group_id).await {
+                log::error!("failed to rebuild leaderboard of {}: {}", group_id, e.to_string());
+            }
+        }
+    }
+}
+
+/// Publishes queued ads whose time has come.
+async fn publish_scheduled_ads(config: &AppConfig, group_id: ChatId) -> RedisResult<()> {
+    let client = &config.redis_client;
+
+    for id in scheduled::due(group_id, ads::now(), client)? {
+        let Some(scheduled_ad) = scheduled::load(group_id, id, client)? else { continue };
+
+        // whoever takes the ad out of the queue publishes it
+        if !scheduled::remove(group_id, &scheduled_ad, client)? { continue }
+
+        let mut sw_user = scheduled_ad.author.clone().in_group(config, group_id).await;
+
+        // authors who left the group don't get to post in it
+        match sw_user.is_group_member().await {
+            Ok(true) => {}
+            Ok(false) => {
+                cancel_scheduled(config, &scheduled_ad, NOT_A_MEMBER).await;
+                continue;
+            }
+            Err(e) => {
+                log::error!("member check failed: {}", e.to_string());
+                retry_or_give_up(config, group_id, scheduled_ad).await?;
+                continue;
+            }
+        }
+
+        // the ad waits for its turn if the author posted something else recently,
+        // it stays in the queue on errors, since it's out of it now
+        let wait = match sw_user.reserve_posting_cooldown().await {
+            Ok(wait) => wait,
+            Err(e) => {
+                scheduled::save(group_id, &scheduled_ad, client)?;
+                return Err(e);
+            }
+        };
+        if let Some(wait) = wait {
+            let mut scheduled_ad = scheduled_ad;
+            scheduled_ad.publish_at = ads::now() + wait;
+            scheduled::save(group_id, &scheduled_ad, client)?;
+            continue;
+        }
+
+        if let Err(e) = publish_scheduled(config, &scheduled_ad, &mut sw_user).await {
+            log::error!("failed to publish scheduled ad {}: {}", id, e.to_string());
+            if let Err(e) = sw_user.release_posting_cooldown().await {
+                log::error!("failed to release posting cooldown: {}", e.to_string());
+            }
+            retry_or_give_up(config, group_id, scheduled_ad).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Puts the ad that failed to get published back to the queue, or gives it back to the author
+/// once it failed too many times.
+async fn retry_or_give_up(config: &AppConfig, group_id: ChatId, mut scheduled_ad: ScheduledAd) -> RedisResult<()> {
+    scheduled_ad.attempts += 1;
+    if scheduled_ad.attempts < MAX_PUBLISH_ATTEMPTS {
+        scheduled_ad.publish_at = ads::now() + PUBLISH_RETRY_SECS;
+        return scheduled::save(group_id, &scheduled_ad, &config.redis_client);
+    }
+
+    cancel_scheduled(config, &scheduled_ad, "Не удалось опубликовать запланированное объявление. \
+        Его можно опубликовать заново через мини-приложение.").await;
+
+    Ok(())
+}
+
+/// Gives an ad that is out of the queue back to the author, explaining why with `reason`.
+pub async fn cancel_scheduled(config: &AppConfig, scheduled_ad: &ScheduledAd, reason: &str) {
+    let author = scheduled_ad.author.id;
+    if let Some(report_id) = scheduled_ad.report_msg_id {
+        if let Err(e) = config.bot.delete_message(author, report_id).await {
+            log::warn!("failed to delete report of scheduled ad: {}", e.to_string());
+        }
+    }
+
+    let text = format!("{}\n\n{}", reason, scheduled_ad.form);
+    if let Err(e) = config.bot.send_message(author, text).parse_mode(ParseMode::Html).await {
+        log::warn!("failed to notify author of unpublished ad: {}", e.to_string());
+    }
+}
+
+/// Re-renders ads of users whose star count changed while their ads were throttled.
+async fn refresh_pending_badges(config: &AppConfig, group_id: ChatId) -> RedisResult<()> {
+
+    for user_id in badges::take_pending(group_id, BADGE_REFRESH_INTERVAL_SECS, &config.redis_client)? {
+        let user = match config.bot.get_chat_member(group_id, user_id).await {
+            Ok(member) => member.user,
+            Err(e) => {
+                log::warn!("failed to get member {}: {}", user_id, e.to_string());
+                continue
+            }
+        };
+
+        let mut sw_user = user.in_group(config, group_id).await;
+        refresh_ads(config, &mut sw_user).await;
+    }
+
+    Ok(())
+}
+
+/// Lets the author of an expired ad with no record know it's gone. There is nothing to renew it from.
+async fn expire_legacy_ad(config: &AppConfig, group_id: ChatId, msg_id: MessageId) -> RedisResult<()> {
+    let client = &config.redis_client;
+    let Some(author) = ads::take_legacy_author(group_id, msg_id, client)? else { return Ok(()) };
+    ads::unset_author(group_id, author, msg_id, client)?;
+
+    let text = "Срок публикации объявления истёк, и оно было снято из группы. \
+        Его можно опубликовать заново через мини-приложение.";
+    if let Err(e) = config.bot.send_message(author, text).await {
+        log::warn!("failed to notify author of expired ad: {}", e.to_string());
+    }
+
+    Ok(())
+}
+
+/// Removes ads older than the group's ad lifetime and offers authors to renew them.
+async fn expire_ads(config: &AppConfig, group_id: ChatId) -> RedisResult<()> {
+    let client = &config.redis_client;
+
+    let Some(ttl) = config.settings_of(group_id).ad_ttl.filter(|ttl| *ttl > 0) else { return Ok(()) };
+    let expired = ads::published_before(group_id, ads::now().saturating_sub(ttl), client)?;
+
+    for msg_id in expired {
+        ads::unmark_published(group_id, msg_id, client)?;
+        if let Err(e) = config.bot.delete_message(group_id, msg_id).await {
+            log::warn!("failed to delete expired ad {}: {}", msg_id, e.to_string());
+        }
+
+        let Some(mut ad) = ads::load(group_id, msg_id, client)? else {
+            expire_legacy_ad(config, group_id, msg_id).await?;
+            continue
+        };
+        ads::unset_author(group_id, ad.author, msg_id, client)?;
+        ad.status = AdStatus::Expired;
+        ads::save(group_id, &ad, client)?;
+
+        // controls of the old report point to a message that is gone
+        if let Some(report_id) = ad.report_msg_id {
+            if let Err(e) = config.bot.edit_message_reply_markup(ad.author, report_id).await {
+                log::warn!("failed to clear report of expired ad: {}", e.to_string());
+            }
+        }
+
+        let text = format!(
+            "Срок публикации объявления истёк, и оно было снято из группы. \
+            Его можно опубликовать заново кнопкой ниже.\n\n{}",
+            ad.form,
+        );
+        if let Err(e) = config.bot.send_message(ad.author, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(make_signed_kb(
+                vec![vec![("Продлить 🔄".to_string(), Renew(msg_id))]],
+                group_id,
+                ad.author,
+                config.callback_key(),
+            ))
+            .await {
+            log::warn!("failed to notify author of expired ad: {}", e.to_string());
+        }
+    }
+
+    Ok(())
+}
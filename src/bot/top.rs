@@ -0,0 +1,71 @@
+use redis::RedisResult;
+use teloxide::prelude::*;
+use crate::store::top::{self, TopEntry};
+use crate::types::{AppConfig, ToSwappyUser};
+use super::TOP_CACHE_SECS;
+
+/// Users on a single page of /top.
+pub const TOP_PAGE_SIZE: usize = 10;
+/// Users the leaderboard is cut at, so building it takes a bounded number of requests.
+const TOP_SIZE: usize = 10 * TOP_PAGE_SIZE;
+
+/// Group members ranked by star count, as of the last [`rebuild`].
+/// `None` if the leaderboard hasn't been built yet.
+pub fn leaderboard(config: &AppConfig, group_id: ChatId) -> RedisResult<Option<Vec<TopEntry>>> {
+    top::load_cached(group_id, &config.redis_client)
+}
+
+/// Ranks the members anew if the leaderboard is older than [`TOP_CACHE_SECS`].
+/// Takes a request per ranked user, so it's meant for the scheduler, not for commands.
+pub async fn rebuild(config: &AppConfig, group_id: ChatId) -> RedisResult<()> {
+    let client = &config.redis_client;
+    if top::is_fresh(group_id, client)? { return Ok(()) }
+
+    let mut entries = vec![];
+    for (user_id, stars) in top::star_counts(group_id, config.settings_of(group_id).star_window, client)? {
+        if entries.len() == TOP_SIZE { break }
+
+        // stars of those who left the group stay, but they don't compete
+        let user = match config.bot.get_chat_member(group_id, user_id).await {
+            Ok(member) if member.is_present() => member.user,
+            _ => continue,
+        };
+
+        let link = user.in_group(config, group_id).await.to_string();
+        entries.push(TopEntry { id: user_id, link, stars });
+    }
+
+    top::save_cached(group_id, &entries, TOP_CACHE_SECS, client)
+}
+
+/// Number of pages the leaderboard takes, at least one.
+pub fn page_count(top: &[TopEntry]) -> usize {
+    top.len().div_ceil(TOP_PAGE_SIZE).max(1)
+}
+
+pub fn page(top: &[TopEntry], page: usize) -> &[TopEntry] {
+    let start = page.saturating_mul(TOP_PAGE_SIZE).min(top.len());
+    let end = (start + TOP_PAGE_SIZE).min(top.len());
+    &top[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::prelude::*;
+    use crate::store::top::TopEntry;
+    use super::{page, page_count};
+
+    #[test]
+    fn leaderboard_is_paginated() {
+        let top: Vec<_> = (0..25)
+            .map(|i| TopEntry { id: UserId(i), link: String::new(), stars: 100 - i as usize })
+            .collect();
+
+        assert_eq!(page_count(&top), 3);
+        assert_eq!(page(&top, 2).len(), 5);
+        assert_eq!(page(&top, 1)[0].id, UserId(10));
+        assert!(page(&top, 3).is_empty());
+        assert!(page(&top, usize::MAX).is_empty());
+        assert_eq!(page_count(&[]), 1);
+    }
+}
@@ -2,11 +2,18 @@ use teloxide::dispatching::{DpHandlerDescription, HandlerExt, UpdateFilterExt};
 use teloxide::{dptree, RequestError};
 use teloxide::dptree::Handler;
 use teloxide::prelude::{DependencyMap, Update};
+use teloxide::types::AllowedUpdate;
 
 use super::handlers::*;
 use super::commands::*;
 use super::filters::*;
 
+/// Kinds of updates the handler takes. Telegram doesn't send `chat_member` ones unless asked.
+pub const ALLOWED_UPDATES: [AllowedUpdate; 3] = [
+    AllowedUpdate::Message,
+    AllowedUpdate::CallbackQuery,
+    AllowedUpdate::ChatMember,
+];
 
 pub fn build_handler() -> Handler<'static, DependencyMap, Result<(), RequestError>, DpHandlerDescription> {
     dptree::entry()
@@ -15,16 +22,27 @@ pub fn build_handler() -> Handler<'static, DependencyMap, Result<(), RequestErro
                 .branch(dptree::entry()
                     .filter_command::<SimpleCommand>()
                     .endpoint(handle_simple_command))
+                .branch(dptree::filter(msg_from_moderator)
+                    .filter_command::<ModeratorCommand>()
+                    .endpoint(handle_moderator_command))
                 .branch(dptree::filter(msg_from_maintainer)
                     .filter_command::<MaintainerCommand>()
                     .endpoint(handle_maintainer_command))
+                .branch(dptree::filter(msg_from_owner)
+                    .filter_command::<OwnerCommand>()
+                    .endpoint(handle_owner_command))
                 .branch(dptree::filter(me_added_to_group)
                     .endpoint(handle_added_to_group))
                 .branch(dptree::filter(has_shared_users).endpoint(handle_shared_users))
+                .branch(dptree::filter(is_private_text).endpoint(handle_complaint_reason))
 
         )
         .branch(
             Update::filter_callback_query()
                 .endpoint(handle_callback_query)
         )
+        .branch(
+            Update::filter_chat_member()
+                .endpoint(handle_chat_member)
+        )
 }
\ No newline at end of file
@@ -0,0 +1,370 @@
+//! Deployment configuration: a TOML file with environment variable overrides.
+//!
+//! Every key of the file can be overridden by the environment variable of the same name
+//! in upper case, e.g. `bot_token` by `BOT_TOKEN`. Lists are comma-separated in the environment.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use redis::IntoConnectionInfo;
+use teloxide::types::UserId;
+use url::Url;
+use crate::store::MONTH_SECS;
+use crate::types::{GroupSettings, PostingLimits, StarRules};
+
+/// Where the config file is looked for unless `CONFIG_FILE` says otherwise.
+pub const DEFAULT_CONFIG_FILE: &str = "swappy.toml";
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+const KEYS: &[&str] = &[
+    "mode",
+    "listen_addr",
+    "bot_domain",
+    "webhook_path",
+    "replace_webhook",
+    "app_domain",
+    "cors_origins",
+    "log_level",
+    "redis_url",
+    "bot_token",
+    "bot_maintainer",
+    "callback_secret",
+    "star_salt",
+    "legacy_star_salts",
+    "max_active_ads",
+    "min_post_interval_mins",
+    "stars_per_extra_ad",
+    "star_window_months",
+    "star_min_member_days",
+    "star_min_giver_stars",
+    "star_daily_budget",
+];
+
+/// How the bot gets updates from Telegram.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunMode {
+    /// Telegram pushes updates to a public HTTPS url
+    Webhook,
+    /// The bot asks Telegram for updates, so it runs anywhere, e.g. on a laptop
+    Polling,
+}
+
+impl FromStr for RunMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webhook" => Ok(RunMode::Webhook),
+            "polling" => Ok(RunMode::Polling),
+            _ => Err("expected webhook or polling"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub mode: RunMode,
+    /// Address the site api is served at, along with the webhook in webhook mode
+    pub listen_addr: SocketAddr,
+    /// Public url Telegram sends updates to, only needed in webhook mode
+    pub bot_url: Option<Url>,
+    /// Path the webhook is served at, the path of `bot_url` if not set
+    pub webhook_path: Option<String>,
+    /// Whether polling may delete the webhook of a deployed instance
+    pub replace_webhook: bool,
+    /// Url of the mini app
+    pub app_url: Url,
+    /// Origins allowed to call the site api, the mini app origin first
+    pub cors_origins: Vec<String>,
+    /// Filters in `RUST_LOG` syntax
+    pub log_level: String,
+    pub redis_url: Url,
+    pub bot_token: String,
+    pub bot_maintainer: UserId,
+    /// Key inline buttons are signed with, so a leaked bot token can't forge them
+    pub callback_secret: String,
+    /// Salt of star hashes, so a leaked database doesn't tell who starred whom
+    pub star_salt: String,
+    /// Salts stars were hashed with before, recognized until re-keyed with /rekeystars
+    pub legacy_star_salts: Vec<String>,
+    /// Settings of groups that didn't override them
+    pub default_settings: GroupSettings,
+}
+
+impl Config {
+    /// Reads the file named by `CONFIG_FILE`, or [`DEFAULT_CONFIG_FILE`] if it exists,
+    /// and applies the environment on top of it.
+    pub fn load() -> Result<Config, Vec<String>> {
+        let (path, required) = match std::env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+
+        let file = match std::fs::read_to_string(&path) {
+            Ok(file) => Some(file),
+            Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(vec![format!("{}: {}", path, e)]);
+            }
+            Err(_) => None,
+        };
+
+        Config::from_sources(file.as_deref(), |key| std::env::var(key).ok())
+    }
+
+    /// Builds the config out of file contents and an environment lookup.
+    /// Returns every invalid field at once.
+    pub fn from_sources(file: Option<&str>, env: impl Fn(&str) -> Option<String>) -> Result<Config, Vec<String>> {
+        let mut fields = Fields::default();
+
+        if let Some(file) = file {
+            match file.parse::<toml::Table>() {
+                Ok(table) => {
+                    for (key, value) in table {
+                        if !KEYS.contains(&key.as_str()) {
+                            fields.errors.push(format!("{}: unknown field", key));
+                            continue;
+                        }
+                        match flatten(&value) {
+                            Some(value) => { fields.values.insert(key, value); }
+                            None => fields.errors.push(format!("{}: expected a string, a number or a list", key)),
+                        }
+                    }
+                }
+                Err(e) => return Err(vec![format!("config file: {}", e)]),
+            }
+        }
+
+        for key in KEYS {
+            if let Some(value) = env(&key.to_uppercase()) {
+                fields.values.insert(key.to_string(), value);
+            }
+        }
+
+        let mode = fields.or("mode", RunMode::Webhook);
+        let listen_addr = fields.or("listen_addr", SocketAddr::from(([0, 0, 0, 0], 8443)));
+        let bot_url: Option<Url> = match mode {
+            RunMode::Webhook => fields.required("bot_domain"),
+            RunMode::Polling => fields.optional("bot_domain"),
+        };
+        let webhook_path = fields.optional::<String>("webhook_path");
+        let replace_webhook = fields.or("replace_webhook", false);
+        let app_url: Option<Url> = fields.required("app_domain");
+        let cors_origins = fields.list("cors_origins");
+        let log_level = fields.or("log_level", env("RUST_LOG").unwrap_or_else(|| "info".to_string()));
+        let redis_url = fields.required("redis_url");
+        let bot_token = fields.required("bot_token");
+        let bot_maintainer = fields.required("bot_maintainer").map(UserId);
+        let callback_secret = fields.required("callback_secret");
+        let star_salt = fields.required("star_salt");
+        let legacy_star_salts = fields.list("legacy_star_salts");
+
+        let defaults = GroupSettings::default();
+        let posting_limits = PostingLimits {
+            max_active_ads: fields.or("max_active_ads", defaults.posting_limits.max_active_ads),
+            min_interval: fields.duration("min_post_interval_mins", 60)
+                .unwrap_or(defaults.posting_limits.min_interval),
+            stars_per_extra_ad: fields.optional("stars_per_extra_ad"),
+        };
+        let star_rules = StarRules {
+            min_membership: fields.duration("star_min_member_days", DAY_SECS).unwrap_or_default(),
+            min_giver_stars: fields.optional("star_min_giver_stars").unwrap_or_default(),
+            daily_budget: fields.optional("star_daily_budget"),
+        };
+        let star_window = fields.duration("star_window_months", MONTH_SECS);
+
+        // ids start at 1, 0 is what the example file leaves
+        if bot_maintainer == Some(UserId(0)) {
+            fields.errors.push("bot_maintainer: should be the Telegram user id of the owner".to_string());
+        }
+
+        // a URL can still be something redis doesn't connect to, e.g. of another scheme
+        if let Some(Err(e)) = redis_url.as_ref().map(|url: &Url| url.as_str().into_connection_info()) {
+            fields.errors.push(format!("redis_url: {}", e));
+        }
+
+        if let Some(path) = &webhook_path {
+            if !path.starts_with('/') {
+                fields.errors.push("webhook_path: should start with /".to_string());
+            }
+        }
+
+        if !fields.errors.is_empty() {
+            return Err(fields.errors);
+        }
+
+        // required fields are all there if nothing went wrong
+        let app_url: Url = app_url.unwrap();
+        let mut cors_origins = cors_origins;
+        let app_origin = app_url.origin().ascii_serialization();
+        cors_origins.retain(|origin| *origin != app_origin);
+        cors_origins.insert(0, app_origin);
+
+        Ok(Config {
+            mode,
+            listen_addr,
+            bot_url,
+            webhook_path,
+            replace_webhook,
+            app_url,
+            cors_origins,
+            log_level,
+            redis_url: redis_url.unwrap(),
+            bot_token: bot_token.unwrap(),
+            bot_maintainer: bot_maintainer.unwrap(),
+            callback_secret: callback_secret.unwrap(),
+            star_salt: star_salt.unwrap(),
+            legacy_star_salts,
+            default_settings: GroupSettings {
+                posting_limits,
+                star_window,
+                star_rules,
+                ..defaults
+            },
+        })
+    }
+}
+
+/// Raw values of the fields and the problems found with them so far.
+#[derive(Default)]
+struct Fields {
+    values: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl Fields {
+    /// Empty values count as not set.
+    fn optional<T: FromStr>(&mut self, key: &str) -> Option<T> where T::Err: Display {
+        let value = self.values.get(key).filter(|value| !value.is_empty())?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{}: {}", key, e));
+                None
+            }
+        }
+    }
+
+    fn required<T: FromStr>(&mut self, key: &str) -> Option<T> where T::Err: Display {
+        if self.values.get(key).is_none_or(|value| value.is_empty()) {
+            self.errors.push(format!("{}: missing, set it in the file or {}", key, key.to_uppercase()));
+            return None;
+        }
+        self.optional(key)
+    }
+
+    fn or<T: FromStr>(&mut self, key: &str, default: T) -> T where T::Err: Display {
+        self.optional(key).unwrap_or(default)
+    }
+
+    /// Number of units given in the field, in seconds.
+    fn duration(&mut self, key: &str, unit_secs: u64) -> Option<u64> {
+        let units: u64 = self.optional(key)?;
+        let secs = units.checked_mul(unit_secs);
+        if secs.is_none() {
+            self.errors.push(format!("{}: too large", key));
+        }
+        secs
+    }
+
+    fn list(&mut self, key: &str) -> Vec<String> {
+        self.values.get(key).map(|value| {
+            value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+        }).unwrap_or_default()
+    }
+}
+
+/// Brings a TOML value to the form the same field takes in the environment.
+fn flatten(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(items) => {
+            let items: Option<Vec<_>> = items.iter().map(flatten).collect();
+            Some(items?.join(","))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use teloxide::types::UserId;
+    use super::{Config, RunMode};
+
+    const FILE: &str = r#"
+        bot_domain = "https://bot.example.com/webhook"
+        app_domain = "https://app.example.com"
+        redis_url = "redis://localhost"
+        bot_token = "123:abc"
+        bot_maintainer = 42
+        callback_secret = "s3cret"
+        star_salt = "pepper"
+        cors_origins = ["https://dev.example.com"]
+        star_window_months = 6
+    "#;
+
+    #[test]
+    fn file_is_read_and_env_wins() {
+        let env = HashMap::from([("LISTEN_ADDR", "127.0.0.1:8080"), ("BOT_MAINTAINER", "7")]);
+        let config = Config::from_sources(Some(FILE), |key| env.get(key).map(|v| v.to_string())).unwrap();
+
+        assert_eq!(config.mode, RunMode::Webhook);
+        assert_eq!(config.listen_addr.port(), 8080);
+        assert_eq!(config.bot_maintainer, UserId(7));
+        assert_eq!(config.cors_origins, ["https://app.example.com", "https://dev.example.com"]);
+        assert!(config.default_settings.star_window.is_some());
+    }
+
+    #[test]
+    fn example_is_valid() {
+        let mut env = HashMap::from([("BOT_TOKEN", "123:abc"), ("CALLBACK_SECRET", "s3cret"), ("STAR_SALT", "pepper")]);
+        let example = include_str!("../swappy.example.toml");
+
+        // the owner has to be filled in
+        let errors = Config::from_sources(Some(example), |key| env.get(key).map(|v| v.to_string())).unwrap_err();
+        assert!(errors.iter().all(|e| e.starts_with("bot_maintainer")), "{:?}", errors);
+
+        env.insert("BOT_MAINTAINER", "42");
+        let config = Config::from_sources(Some(example), |key| env.get(key).map(|v| v.to_string())).unwrap();
+
+        assert_eq!(config.star_salt, "pepper");
+        assert!(config.legacy_star_salts.is_empty());
+        assert_eq!(config.default_settings.posting_limits.max_active_ads, 3);
+    }
+
+    #[test]
+    fn polling_needs_no_public_url() {
+        let file = FILE.replace("bot_domain", "# bot_domain");
+        let env = HashMap::from([("MODE", "polling")]);
+        let config = Config::from_sources(Some(&file), |key| env.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(config.mode, RunMode::Polling);
+        assert!(!config.replace_webhook);
+
+        let env = HashMap::from([("MODE", "pigeons")]);
+        let errors = Config::from_sources(Some(&file), |key| env.get(key).map(|v| v.to_string())).unwrap_err();
+        assert!(errors.iter().any(|e| e.starts_with("mode")));
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let env = HashMap::from([
+            ("BOT_MAINTAINER", "nobody"),
+            ("LISTEN_ADDR", "everywhere"),
+            ("STAR_WINDOW_MONTHS", "18446744073709551615"),
+            ("REDIS_URL", "https://localhost"),
+        ]);
+        let errors = Config::from_sources(Some("colour = \"blue\""), |key| env.get(key).map(|v| v.to_string()))
+            .unwrap_err();
+
+        for field in [
+            "colour", "listen_addr", "bot_domain", "app_domain", "redis_url", "bot_token", "bot_maintainer",
+            "callback_secret", "star_salt", "star_window_months",
+        ] {
+            assert!(errors.iter().any(|e| e.starts_with(field)), "{} is not reported in {:?}", field, errors);
+        }
+    }
+}
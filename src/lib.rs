@@ -1,10 +1,17 @@
+#[allow(unused_imports)]
 use std::sync::atomic::Ordering;
+#[allow(unused_imports)]
 use redis::{Commands, RedisResult};
+#[allow(unused_imports)]
 use teloxide::prelude::UserId;
+#[allow(unused_imports)]
 use teloxide::RequestError;
+#[allow(unused_imports)]
 use teloxide::types::{ChatId, MessageId};
+#[allow(unused_imports)]
 use crate::types::AppConfig;
 
+pub mod config;
 pub mod types;
 pub mod bot;
 pub mod site;
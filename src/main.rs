@@ -1,51 +1,72 @@
-use std::env;
-use std::sync::atomic::AtomicI64;
-use std::sync::Arc;
+use std::fmt::Debug;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use axum::Router;
 use teloxide::prelude::*;
-use teloxide::update_listeners::UpdateListener;
+use teloxide::stop::StopToken;
+use teloxide::update_listeners::{Polling, UpdateListener};
 
-use redis;
-use redis::Commands;
 use teloxide::types::{MenuButton, WebAppInfo};
 use teloxide::update_listeners;
 use teloxide::update_listeners::webhooks::Options;
 use teloxide::utils::command::BotCommands;
 use update_listeners::webhooks;
-use webhooks::axum_to_router;
+use webhooks::axum_no_setup;
 use swappy2::bot;
-use swappy2::bot::TARGET_GROUP_ID_KEY;
+use swappy2::bot::ALLOWED_UPDATES;
+use swappy2::store;
 use swappy2::site::add_routes;
+use swappy2::config::{Config, RunMode};
 use swappy2::types::AppConfig;
-use url::Url;
 use swappy2::bot::commands::SimpleCommand;
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
-
-    let bot_url = env::var("BOT_DOMAIN").expect("expected BOT_DOMAIN")
-        .parse().expect("BOT_DOMAIN should be valid url");
-
-    let redis_url = env::var("REDIS_URL").expect("expected REDIS_URL")
-        .parse::<Url>().expect("REDIS_URL should be valid url");
-
-    let client = redis::Client::open(redis_url).unwrap();
-    let mut con = client.get_connection().expect("redis should be running");
-    let group_id: i64 = con.get(TARGET_GROUP_ID_KEY).unwrap_or_default();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("invalid configuration:");
+            errors.iter().for_each(|e| eprintln!("  {}", e));
+            std::process::exit(1);
+        }
+    };
 
-    let bot_token = env::var("BOT_TOKEN").expect("expected BOT_TOKEN");
-    let maintainer_id = env::var("BOT_MAINTAINER").expect("expected BOT_MAINTAINER")
-        .parse::<u64>().expect("BOT_MAINTAINER should be u64");
+    pretty_env_logger::formatted_builder().parse_filters(&config.log_level).init();
+
+    let client = redis::Client::open(config.redis_url.as_str()).expect("redis_url should be checked with the config");
+    let groups = store::groups::load(&client).expect("redis should be running");
+    let roles = store::roles::load(&client).expect("redis should be running");
+
+    let bot_token = config.bot_token;
+    let mut star_salts: Vec<_> = std::iter::once(config.star_salt)
+        .chain(config.legacy_star_salts)
+        .collect();
+    // stars given before star_salt existed are salted with the token until they are re-keyed
+    let rekeyed = store::stars_rekeyed(&client).expect("redis should be running");
+    if !rekeyed && !star_salts.contains(&bot_token) {
+        star_salts.push(bot_token.clone());
+    }
+    let star_salts = star_salts.into_iter().map(String::into_bytes).collect();
+
+    let (mode, addr) = (config.mode, config.listen_addr);
+    let (bot_url, webhook_path, replace_webhook) = (config.bot_url, config.webhook_path, config.replace_webhook);
     let config = Arc::new(AppConfig {
-        app_url: env::var("APP_DOMAIN").expect("expected APP_DOMAIN")
-            .parse().expect("APP_DOMAIN should be valid url"),
+        app_url: config.app_url,
+        cors_origins: config.cors_origins,
         bot: Bot::new(&bot_token),
         bot_token,
+        callback_secret: config.callback_secret,
         redis_client: client,
-        bot_maintainer: UserId(maintainer_id),
-        group_id: Arc::new(AtomicI64::new(group_id)),
+        bot_maintainer: config.bot_maintainer,
+        groups: RwLock::new(groups),
+        roles: RwLock::new(roles),
+        star_salts,
+        default_settings: config.default_settings,
     });
 
+    store::migrate(&config.redis_client).expect("should be able to migrate the database");
+
     let menu_button = MenuButton::WebApp {
         text: "Swappy".to_string(),
         web_app: WebAppInfo { url: config.app_url.clone() },
@@ -56,37 +77,82 @@ async fn main() {
 
     config.bot.set_my_commands(SimpleCommand::bot_commands()).await.expect("");
 
-    let handler = bot::build_handler();
-    let addr = ([0,0,0,0],8443).into();
-
-    let (mut listener, stop_flag, router) = axum_to_router(
-        config.bot.clone(),
-        Options::new(addr, bot_url),
-    ).await.expect("should be able to set webhook");
-
-
-    let router = add_routes(router, Arc::clone(&config));
-    let stop_token = listener.stop_token();
+    tokio::spawn(bot::scheduler::run(Arc::clone(&config)));
+
+    match mode {
+        RunMode::Webhook => {
+            let mut options = Options::new(addr, bot_url.expect("config should require bot_domain for webhooks"));
+            if let Some(path) = webhook_path { options.path = path; }
+            let (url, secret) = (options.url.clone(), options.get_or_gen_secret_token().to_string());
+
+            // teloxide would set the webhook without allowed updates, which leaves out chat members
+            config.bot.set_webhook(url)
+                .secret_token(secret)
+                .allowed_updates(ALLOWED_UPDATES)
+                .await.expect("should be able to set webhook");
+
+            let (mut listener, stop_flag, router) = axum_no_setup(options);
+            let bot = config.bot.clone();
+            let stop_flag = async move {
+                stop_flag.await;
+                if let Err(e) = bot.delete_webhook().await {
+                    log::error!("couldn't delete webhook: {}", e.to_string());
+                }
+            };
+
+            let router = add_routes(router, Arc::clone(&config));
+            serve(addr, router, stop_flag, listener.stop_token());
+            dispatch(config, listener).await;
+        }
+        RunMode::Polling => {
+            // polling fails while a webhook is set, and deleting it cuts the deployed bot off
+            let webhook = config.bot.get_webhook_info().await.expect("should be able to get webhook info").url;
+            let polling = Polling::builder(config.bot.clone()).allowed_updates(ALLOWED_UPDATES.to_vec());
+            let mut listener = match webhook {
+                None => polling.build(),
+                Some(url) if replace_webhook => {
+                    log::warn!("deleting webhook {}, the bot behind it gets no updates until it restarts", url);
+                    polling.delete_webhook().await.build()
+                }
+                Some(url) => {
+                    log::error!("webhook {} is set, set replace_webhook to delete it and poll anyway", url);
+                    std::process::exit(1);
+                }
+            };
+            log::info!("polling for updates, site api is served at {}", addr);
+
+            let router = add_routes(Router::new(), Arc::clone(&config));
+            serve(addr, router, std::future::pending(), listener.stop_token());
+            dispatch(config, listener).await;
+        }
+    }
+}
 
+/// Serves the site api in the background, stopping updates if the server can't run.
+fn serve(
+    addr: SocketAddr,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    stop_token: StopToken,
+) {
     tokio::spawn(async move {
         let tcp_listener = tokio::net::TcpListener::bind(addr)
-            .await.map_err(|err|
-            {
-                stop_token.stop();
-                err
-            })
+            .await.inspect_err(|_| stop_token.stop())
             .expect("should be able to bind");
 
         axum::serve(tcp_listener, router)
-            .with_graceful_shutdown(stop_flag)
-            .await.map_err(|e|
-            {
-                stop_token.stop();
-                e
-            })
+            .with_graceful_shutdown(shutdown)
+            .await.inspect_err(|_| stop_token.stop())
             .expect("axum server error");
     });
+}
 
+async fn dispatch<L>(config: Arc<AppConfig>, listener: L)
+where
+    L: UpdateListener + Send,
+    L::Err: Debug,
+{
+    let handler = bot::build_handler();
     let error_handler =
         LoggingErrorHandler::with_custom_text("An error from the update listener");
     Dispatcher::builder(config.bot.clone(), handler)
@@ -1,20 +1,43 @@
 use std::sync::Arc;
-use axum::routing::{options, post, Router};
+use axum::routing::{get, options, post, Router};
 
 mod handlers;
 mod init_data;
-mod form;
+pub mod form;
 mod tg;
 
 use handlers::{
+    handle_get_form,
+    handle_get_settings,
+    handle_get_top,
     handle_posting,
+    handle_preview,
     r_options
 };
 
 use crate::types::AppConfig;
 
+pub use tg::{
+    format_deals,
+    make_edit_url,
+    make_report_kb,
+    publish_scheduled,
+    refresh_ads,
+    render_closed_ad,
+    render_closed_text,
+    republish_ad,
+    WARNING_MARK,
+};
+
 pub fn add_routes(router: Router, state: Arc<AppConfig>) -> Router {
     router
         .route("/bot/form", post(handle_posting).with_state(Arc::clone(&state)))
+        .route("/bot/form", get(handle_get_form).with_state(Arc::clone(&state)))
         .route("/bot/form", options(r_options).with_state(Arc::clone(&state)))
+        .route("/bot/form/preview", post(handle_preview).with_state(Arc::clone(&state)))
+        .route("/bot/form/preview", options(r_options).with_state(Arc::clone(&state)))
+        .route("/bot/top", get(handle_get_top).with_state(Arc::clone(&state)))
+        .route("/bot/top", options(r_options).with_state(Arc::clone(&state)))
+        .route("/bot/settings", get(handle_get_settings).with_state(Arc::clone(&state)))
+        .route("/bot/settings", options(r_options).with_state(Arc::clone(&state)))
 }
\ No newline at end of file
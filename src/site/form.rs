@@ -1,7 +1,7 @@
 use std::fmt::{Display, Formatter};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Form {
     buy_or_sell: String,
@@ -29,6 +29,7 @@ pub struct Form {
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use crate::site::form::methods;
 
@@ -71,6 +72,7 @@ mod tests {
     }
 }
 
+#[allow(clippy::ptr_arg, clippy::single_char_add_str)]
 fn methods(methods: &Vec<String>, additional: &str, more: bool, prefix: &str) -> String {
     // who let the overengineers out?
 
@@ -126,6 +128,16 @@ impl Display for Form {
 }
 
 impl Form {
+    /// Currencies the ad deals in, the sold one first.
+    pub fn currencies(&self) -> [&str; 2] {
+        [&self.selling_curr, &self.buying_curr]
+    }
+
+    /// Payment methods picked from those the group offers. Methods typed in by hand aren't there.
+    pub fn payment_methods(&self) -> impl Iterator<Item = &str> {
+        self.eu_methods.iter().chain(&self.ru_methods).map(String::as_str)
+    }
+
     fn is_buying(&self) -> bool {
         self.buy_or_sell == "Купить"
     }
@@ -1,6 +1,9 @@
 use super::init_data;
 use super::tg;
 use crate::site::form::Form;
+use crate::bot::top;
+use crate::store::ads;
+use crate::store::top::TopEntry;
 use crate::types::{AppConfig, SwappyUser, ToSwappyUser};
 use axum::extract::{Query, State};
 use axum::http;
@@ -8,23 +11,51 @@ use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use std::borrow::Borrow;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
-use serde::Deserialize;
-use teloxide::{ApiError, RequestError};
-use teloxide::types::ChatId;
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId, User};
 use tokio::time::Instant;
-use url::Url;
 use init_data::validate;
-use crate::site::init_data::Error;
-use crate::types::swappy_bot::ToSwappyBot;
 
 #[derive(Deserialize, Debug)]
 pub struct PostParams {
     pub edit_id: Option<i32>,
     pub report_id: Option<i32>,
+    /// Unix time to publish a new ad at, instead of publishing it right away
+    pub publish_at: Option<u64>,
+    /// Group the ad goes to, the one chosen in the bot if not set
+    pub group_id: Option<i64>,
+    /// Whether the mini app keeps form data in its CloudStorage.
+    /// Ads are stored server-side now, so the flag is only accepted for older clients.
+    #[serde(default)]
+    #[allow(dead_code)]
     pub keeping: bool,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct AdParams {
+    pub edit_id: i32,
+    pub group_id: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TopParams {
+    #[serde(default)]
+    pub page: usize,
+    pub group_id: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GroupParams {
+    pub group_id: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct TopPage<'a> {
+    page: usize,
+    pages: usize,
+    entries: &'a [TopEntry],
+}
+
 pub async fn handle_posting(
     headers: HeaderMap,
     State(app_config): State<Arc<AppConfig>>,
@@ -34,44 +65,34 @@ pub async fn handle_posting(
     let now = Instant::now();
 
     let mut resp_headers = HeaderMap::new();
-    add_access_control_headers(&mut resp_headers, &app_config.app_url);
+    add_access_control_headers(&mut resp_headers, &headers, &app_config.cors_origins);
 
-    // validate init data
-    let data = if let Some(data) = headers.get("X-Telegram-Init-Data") { data.as_bytes() } else {
-        return (StatusCode::UNAUTHORIZED, resp_headers, String::default())
+    let (mut sw_user, form_data) = match accept_form(&headers, &app_config, query.group_id, &bytes).await {
+        Ok(accepted) => accepted,
+        Err((status, body)) => return (status, resp_headers, body),
     };
 
-    let tg_user =
-        if let Some(user) = validate(data, app_config.bot_token.as_bytes(), false).ok() {
-            user
-        } else {
-            return (StatusCode::UNAUTHORIZED, resp_headers, String::default())
-        };
-
-    // check if user is a part of the group
-    let sw_user = tg_user.with_config(&app_config).await;
-    match sw_user.is_group_member().await {
-        Ok(true) => {} // continue
-        Ok(false) => return (StatusCode::FORBIDDEN, resp_headers, String::default()),
-        Err(e) => {
-            log::error!("member check failed: {}", e.to_string());
-            return (StatusCode::INTERNAL_SERVER_ERROR, resp_headers, "Try later".to_string())
-        }
+    if let Err((status, body)) = tg::check_submission(&query, &mut sw_user).await {
+        return (status, resp_headers, body);
     }
 
-    // parse form
-    let form_data: serde_json::Result<Form> = serde_json::from_slice(&bytes);
-    if form_data.is_err() {
-        return (StatusCode::BAD_REQUEST, resp_headers, "Form error".to_string())
+    // scheduled ads are answered with 202 and id of the queued ad instead of the group message id
+    if let Some(publish_at) = tg::scheduled_time(&query) {
+        return match tg::schedule_ad(app_config.borrow(), publish_at, form_data, sw_user).await {
+            Ok((id, report_id)) => (StatusCode::ACCEPTED, resp_headers, format!("{},{}", id, report_id)),
+            Err((status, body)) => (status, resp_headers, body),
+        };
     }
-    let form_data = form_data.unwrap();
 
-    let (msg_id, report_id) = tg::handle_shit(
+    let (msg_id, report_id) = match tg::handle_shit(
         app_config.borrow(),
         query.0,
         form_data,
         sw_user,
-    ).await.expect("TODO: panic message");
+    ).await {
+        Ok(ids) => ids,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
 
     let elapsed = now.elapsed();
     println!("request took {}microsecs", elapsed.as_micros());
@@ -83,11 +104,149 @@ pub async fn handle_posting(
     )
 }
 
+/// Renders the ad exactly as it would be published, without sending anything to Telegram.
+/// Takes the same parameters as posting and fails the same way.
+pub async fn handle_preview(
+    headers: HeaderMap,
+    State(app_config): State<Arc<AppConfig>>,
+    query: Query<PostParams>,
+    bytes: axum::body::Bytes,
+) -> impl IntoResponse {
+    let mut resp_headers = HeaderMap::new();
+    add_access_control_headers(&mut resp_headers, &headers, &app_config.cors_origins);
+
+    let (mut sw_user, form_data) = match accept_form(&headers, &app_config, query.group_id, &bytes).await {
+        Ok(accepted) => accepted,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
+
+    if let Err((status, body)) = tg::check_submission(&query, &mut sw_user).await {
+        return (status, resp_headers, body);
+    }
+
+    let text = tg::render_ad(&form_data, &mut sw_user).await;
+    resp_headers.insert(header::CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+
+    (StatusCode::OK, resp_headers, text)
+}
+
+/// Returns stored form of an ad, so the mini app can prefill the editor.
+pub async fn handle_get_form(
+    headers: HeaderMap,
+    State(app_config): State<Arc<AppConfig>>,
+    query: Query<AdParams>,
+) -> impl IntoResponse {
+    let mut resp_headers = HeaderMap::new();
+    add_access_control_headers(&mut resp_headers, &headers, &app_config.cors_origins);
+
+    let tg_user = match authenticate(&headers, &app_config) {
+        Ok(user) => user,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
+
+    let group_id = match resolve_group(&headers, &app_config, &tg_user, query.group_id) {
+        Ok(group_id) => group_id,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
+    let mut sw_user = tg_user.in_group(&app_config, group_id).await;
+    let msg_id = MessageId(query.edit_id);
+    match sw_user.is_author(msg_id).await {
+        Ok(true) => {} // continue
+        Ok(false) => return (StatusCode::FORBIDDEN, resp_headers, "Редактируемое сообщение не ваше".to_string()),
+        Err(e) => {
+            log::error!("redis query failed: {}", e.to_string());
+            return (StatusCode::INTERNAL_SERVER_ERROR, resp_headers, "Try later".to_string())
+        }
+    }
+
+    match ads::load(sw_user.group_id, msg_id, &app_config.redis_client) {
+        Ok(Some(ad)) => {
+            resp_headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+            let json = serde_json::to_string(&ad.form).expect("form should be serializable");
+            (StatusCode::OK, resp_headers, json)
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, resp_headers, "Данные объявления не сохранены".to_string()),
+        Err(e) => {
+            log::error!("failed to load ad: {}", e.to_string());
+            (StatusCode::INTERNAL_SERVER_ERROR, resp_headers, "Try later".to_string())
+        }
+    }
+}
+
+/// Page of the group leaderboard, same as /top shows in the bot.
+pub async fn handle_get_top(
+    headers: HeaderMap,
+    State(app_config): State<Arc<AppConfig>>,
+    query: Query<TopParams>,
+) -> impl IntoResponse {
+    let mut resp_headers = HeaderMap::new();
+    add_access_control_headers(&mut resp_headers, &headers, &app_config.cors_origins);
+
+    let tg_user = match authenticate(&headers, &app_config) {
+        Ok(user) => user,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
+
+    let group_id = match resolve_group(&headers, &app_config, &tg_user, query.group_id) {
+        Ok(group_id) => group_id,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
+
+    // names and links of members are for members only
+    let sw_user = tg_user.in_group(&app_config, group_id).await;
+    if let Err((status, body)) = check_membership(&sw_user).await {
+        return (status, resp_headers, body);
+    }
+
+    let leaderboard = match top::leaderboard(&app_config, group_id) {
+        Ok(Some(leaderboard)) => leaderboard,
+        Ok(None) => return (StatusCode::SERVICE_UNAVAILABLE, resp_headers, "Рейтинг ещё составляется".to_string()),
+        Err(e) => {
+            log::error!("failed to load leaderboard: {}", e.to_string());
+            return (StatusCode::INTERNAL_SERVER_ERROR, resp_headers, "Try later".to_string())
+        }
+    };
+
+    let pages = top::page_count(&leaderboard);
+    let page = query.page.min(pages - 1);
+    let page = TopPage {
+        page,
+        pages,
+        entries: top::page(&leaderboard, page),
+    };
+    resp_headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    let json = serde_json::to_string(&page).expect("leaderboard should be serializable");
+    (StatusCode::OK, resp_headers, json)
+}
+
+/// Currencies, payment methods and other settings of the group the mini app builds the form from.
+pub async fn handle_get_settings(
+    headers: HeaderMap,
+    State(app_config): State<Arc<AppConfig>>,
+    query: Query<GroupParams>,
+) -> impl IntoResponse {
+    let mut resp_headers = HeaderMap::new();
+    add_access_control_headers(&mut resp_headers, &headers, &app_config.cors_origins);
+
+    let group_id = match authenticate(&headers, &app_config)
+        .and_then(|tg_user| resolve_group(&headers, &app_config, &tg_user, query.group_id))
+    {
+        Ok(group_id) => group_id,
+        Err((status, body)) => return (status, resp_headers, body),
+    };
+
+    let settings = app_config.settings_of(group_id);
+    resp_headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    let json = serde_json::to_string(&settings.public()).expect("settings should be serializable");
+    (StatusCode::OK, resp_headers, json)
+}
+
 pub async fn r_options(
+    headers: HeaderMap,
     State(app_config): State<Arc<AppConfig>>,
 ) -> impl IntoResponse {
     let mut resp_headers = HeaderMap::new();
-    add_access_control_headers(&mut resp_headers, &app_config.app_url);
+    add_access_control_headers(&mut resp_headers, &headers, &app_config.cors_origins);
 
     (
         StatusCode::OK,
@@ -95,10 +254,86 @@ pub async fn r_options(
     )
 }
 
-fn add_access_control_headers(resp_headers: &mut HeaderMap, app_url: &Url) {
+/// Validates init data sent by the mini app and returns the user it belongs to.
+fn authenticate(headers: &HeaderMap, app_config: &AppConfig) -> Result<User, (StatusCode, String)> {
+    let data = if let Some(data) = headers.get("X-Telegram-Init-Data") { data.as_bytes() } else {
+        return Err((StatusCode::UNAUTHORIZED, String::default()))
+    };
+
+    validate(data, app_config.bot_token.as_bytes(), false)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, String::default()))
+}
+
+/// Group the request is about: the one the mini app was opened for, explicitly or through
+/// `start_param` of init data, or the one chosen in the bot.
+fn resolve_group(
+    headers: &HeaderMap,
+    app_config: &AppConfig,
+    tg_user: &User,
+    group_id: Option<i64>,
+) -> Result<ChatId, (StatusCode, String)> {
+    let group_id = group_id.or_else(|| {
+        let data = headers.get("X-Telegram-Init-Data")?.as_bytes();
+        init_data::start_param(data)?.parse().ok()
+    });
+
+    match group_id.map(ChatId) {
+        Some(group_id) if app_config.is_registered(group_id) => Ok(group_id),
+        Some(_) => Err((StatusCode::BAD_REQUEST, "Бот не обслуживает эту группу".to_string())),
+        None => Ok(app_config.group_of(tg_user.id)),
+    }
+}
+
+/// Everything a submitted form goes through before it can become an ad:
+/// init data validation, group membership check and parsing.
+async fn accept_form<'a>(
+    headers: &HeaderMap,
+    app_config: &'a AppConfig,
+    group_id: Option<i64>,
+    bytes: &[u8],
+) -> Result<(SwappyUser<'a>, Form), (StatusCode, String)> {
+    let tg_user = authenticate(headers, app_config)?;
+    let group_id = resolve_group(headers, app_config, &tg_user, group_id)?;
+
+    // check if user is a part of the group
+    let sw_user = tg_user.in_group(app_config, group_id).await;
+    check_membership(&sw_user).await?;
+
+    // parse form
+    let form_data: Form = serde_json::from_slice(bytes)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Form error".to_string()))?;
+
+    if let Some(currency) = form_data.currencies().into_iter().find(|c| !sw_user.settings.allows_currency(c)) {
+        return Err((StatusCode::BAD_REQUEST, format!("В группе не публикуются объявления с валютой {}", currency)));
+    }
+    if let Some(method) = form_data.payment_methods().find(|m| !sw_user.settings.allows_payment_method(m)) {
+        return Err((StatusCode::BAD_REQUEST, format!("Способа оплаты {} нет среди предложенных группой", method)));
+    }
+
+    Ok((sw_user, form_data))
+}
+
+async fn check_membership(sw_user: &SwappyUser<'_>) -> Result<(), (StatusCode, String)> {
+    match sw_user.is_group_member().await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::FORBIDDEN, String::default())),
+        Err(e) => {
+            log::error!("member check failed: {}", e.to_string());
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Try later".to_string()))
+        }
+    }
+}
+
+/// Allows the origin of the request if it's one of `origins`, the first of them otherwise.
+fn add_access_control_headers(resp_headers: &mut HeaderMap, headers: &HeaderMap, origins: &[String]) {
     resp_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, "X-Telegram-Init-Data".parse().unwrap());
-    resp_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, http::Method::POST.as_str().parse().unwrap());
+    let methods = format!("{}, {}", http::Method::GET, http::Method::POST);
+    resp_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods.parse().unwrap());
 
-    let origin = app_url.origin().ascii_serialization();
+    let requested = headers.get(header::ORIGIN).and_then(|origin| origin.to_str().ok());
+    let origin = requested.filter(|requested| origins.iter().any(|origin| origin == requested))
+        .or(origins.first().map(String::as_str))
+        .unwrap_or_default();
     resp_headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.parse().unwrap());
+    resp_headers.insert(header::VARY, "Origin".parse().unwrap());
 }
\ No newline at end of file
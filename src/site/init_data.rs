@@ -10,6 +10,7 @@ use teloxide::types::{
 };
 
 #[derive(Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Error {
     BadData(serde_urlencoded::de::Error),
     BadArgs,
@@ -23,6 +24,7 @@ pub enum Error {
 ///
 /// Errors
 /// TooOld if token is older than 30 minutes
+#[allow(clippy::len_zero, clippy::needless_borrow, clippy::single_char_add_str)]
 pub fn validate(data: &[u8], token: &[u8], ignore_age: bool) -> Result<User, Error> {
     use Error::*;
 
@@ -88,6 +90,13 @@ pub fn validate(data: &[u8], token: &[u8], ignore_age: bool) -> Result<User, Err
     Ok(serde_json::from_str::<WebAppUser>(&u).unwrap().into())
 }
 
+/// `start_param` of init data, set when the mini app is opened through a link with `startapp`.
+/// Doesn't validate the data, so call it after [`validate`].
+pub fn start_param(data: &[u8]) -> Option<String> {
+    let mut pairs: HashMap<String, String> = serde_urlencoded::from_bytes(data).ok()?;
+    pairs.remove("start_param")
+}
+
 #[derive(Deserialize, Debug)]
 struct WebAppUser {
     id: u64,
@@ -96,6 +105,7 @@ struct WebAppUser {
     username: Option<String>,
     language_code: Option<String>,
     is_premium: Option<bool>,
+    #[allow(dead_code)]
     allows_write_to_pm: bool,
 }
 
@@ -119,7 +129,7 @@ mod tests {
     use teloxide::prelude::*;
     use teloxide::types::User;
     use super::Error::TooOld;
-    use super::{validate, WebAppUser};
+    use super::{start_param, validate, WebAppUser};
 
     #[test]
     fn user_parsed_successfully() {
@@ -156,4 +166,10 @@ mod tests {
         let res = validate(init_data, token, true);
         assert!(res.is_ok())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn start_param_is_extracted() {
+        assert_eq!(start_param(b"start_param=-1001234567890&auth_date=1"), Some("-1001234567890".to_string()));
+        assert_eq!(start_param(b"auth_date=1"), None);
+    }
+}
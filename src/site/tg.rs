@@ -1,64 +1,114 @@
-use std::fmt::format;
 use crate::site::form::Form;
-use crate::types::{AppConfig, SwappyUser};
-use std::sync::atomic::Ordering;
+use crate::types::{AppConfig, Rejection, SwappyUser};
 use axum::http::StatusCode;
-use redis::{Commands, RedisError};
 use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode, User, WebAppInfo};
-use teloxide::RequestError;
+use teloxide::{ApiError, RequestError};
 use crate::site::handlers::PostParams;
-use crate::store::get_star_count;
-use crate::types::swappy_bot::ToSwappyBot;
+use crate::store::ads::{self, Ad, AdStatus};
+use crate::store::scheduled::{self, ScheduledAd};
+use crate::bot::{format_duration, make_signed_kb};
+use teloxide::utils::html;
+use url::Url;
 
-pub async fn handle_shit(
-    app_config: &AppConfig,
-    post_params: PostParams,
-    form: Form,
-    mut sw_user: SwappyUser<'_>,
-) -> Result<(MessageId, MessageId), (StatusCode, String)> {
-    let mut delete_old_report = false;
+const CLOSED_MARK: &str = "✅ <b>Сделка состоялась</b>";
+/// Marks users with approved complaints against them
+pub const WARNING_MARK: &str = "⚠️";
+const MAX_SCHEDULE_AHEAD_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Checks that a submitted ad would be accepted: the edited ad belongs to the user,
+/// new ads fit posting limits, and scheduled ones are not too far ahead.
+pub async fn check_submission(
+    post_params: &PostParams,
+    sw_user: &mut SwappyUser<'_>,
+) -> Result<(), (StatusCode, String)> {
     if let Some(edit_id) = post_params.edit_id {
-        let edit_id = MessageId(edit_id);
-        match sw_user.is_author(edit_id).await {
-            Ok(true) => {
-                delete_old_report = true;
-            }
-            Ok(false) => {
-                return Err((StatusCode::FORBIDDEN, "Редактируемое сообщение не ваше".to_string()));
-            }
+        // edits don't count against the quota
+        return match sw_user.is_author(MessageId(edit_id)).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err((StatusCode::FORBIDDEN, "Редактируемое сообщение не ваше".to_string())),
             Err(e) => {
                 log::error!("redis query failed: {}", e.to_string());
-                return Err((StatusCode::INTERNAL_SERVER_ERROR,
-                            "Что-то пошло не так, попробуйте позднее".to_string()));
+                Err((StatusCode::INTERNAL_SERVER_ERROR,
+                     "Что-то пошло не так, попробуйте позднее".to_string()))
             }
+        };
+    }
+
+    if let Some(publish_at) = scheduled_time(post_params) {
+        if publish_at > ads::now() + MAX_SCHEDULE_AHEAD_SECS {
+            return Err((StatusCode::BAD_REQUEST,
+                        "Публикацию можно запланировать не больше чем на неделю вперёд".to_string()));
         }
     }
 
+    check_posting_limits(sw_user).await
+}
+
+/// Time a new ad is to be published at, `None` if it goes to the group right away.
+pub fn scheduled_time(post_params: &PostParams) -> Option<u64> {
+    post_params.publish_at
+        .filter(|at| *at > ads::now() + 60)
+        .filter(|_| post_params.edit_id.is_none())
+}
+
+/// Publishes a new ad or edits one, after [`check_submission`].
+pub async fn handle_shit(
+    app_config: &AppConfig,
+    post_params: PostParams,
+    form: Form,
+    mut sw_user: SwappyUser<'_>,
+) -> Result<(MessageId, MessageId), (StatusCode, String)> {
+    let delete_old_report = post_params.edit_id.is_some();
 
     // let sw_bot = app_config.bot.clone().to_swappy_bot(app_config.group_id());
 
-    let group_msg = post_ad(
+    // the interval is taken before posting, so concurrent submissions can't both get through
+    let is_new = post_params.edit_id.is_none();
+    if is_new {
+        reserve_posting_cooldown(&mut sw_user).await?;
+    }
+
+    let group_msg = match post_ad(
         post_params.edit_id,
         &app_config.bot,
         &form,
         &mut sw_user,
-    ).await.map_err(|e| {
-        log::error!("failed to post ad: {}", e.to_string());
-        (StatusCode::INTERNAL_SERVER_ERROR, "Try later".to_string())
-    })?;
+    ).await {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::error!("failed to post ad: {}", e.to_string());
+            if is_new {
+                if let Err(e) = sw_user.release_posting_cooldown().await {
+                    log::error!("failed to release posting cooldown: {}", e.to_string());
+                }
+            }
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Try later".to_string()));
+        }
+    };
 
-    sw_user.set_author(group_msg.id).await.expect("kk");
+    if post_params.edit_id.is_none() {
+        track_new_ad(app_config, &mut sw_user, group_msg.id).await;
+    }
+
+    let stored_ad = if post_params.edit_id.is_some() {
+        ads::load(sw_user.group_id, group_msg.id, &app_config.redis_client)
+            .unwrap_or_else(|e| {
+                log::error!("failed to load ad: {}", e.to_string());
+                None
+            })
+    } else { None };
 
     if delete_old_report {
-        if let Err(e) = app_config.bot.delete_message(
-            sw_user.tg_user.id, MessageId(post_params.report_id.unwrap_or_default()))
-            .await {
+        let old_report_id = post_params.report_id.map(MessageId)
+            .or(stored_ad.as_ref().and_then(|ad| ad.report_msg_id))
+            .unwrap_or(MessageId(0));
+        if let Err(e) = app_config.bot.delete_message(sw_user.tg_user.id, old_report_id).await {
             log::error!("failed to delete old report: {}", e.to_string());
         }
     }
 
-    let report_id = report_ad(&sw_user.tg_user, sw_user.group_id, &group_msg, post_params, app_config).await
+    let report_id = report_ad(&sw_user.tg_user, sw_user.group_id, &group_msg, app_config).await
         .map_err(|e| {
             // if let Err(e) = app_config.bot.delete_message(group_id, group_msg.id).await {
             //     log::error!("failed to cleanup ad after failing to send report: {}", e.to_string());
@@ -66,9 +116,202 @@ pub async fn handle_shit(
             (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
         })?;
 
+    let mut ad = stored_ad.unwrap_or_else(|| Ad::new(sw_user.tg_user.id, group_msg.id, form.clone()));
+    if post_params.edit_id.is_some() {
+        ad.form = form;
+        ad.touch();
+    }
+    ad.report_msg_id = Some(report_id);
+    if let Err(e) = ads::save(sw_user.group_id, &ad, &app_config.redis_client) {
+        log::error!("failed to save ad: {}", e.to_string());
+    }
+
     Ok((group_msg.id, report_id))
 }
 
+/// Puts a new ad into the queue and sends the author a report with its controls,
+/// after [`check_submission`].
+///
+/// Returns id of the scheduled ad and of the report.
+pub async fn schedule_ad(
+    app_config: &AppConfig,
+    publish_at: u64,
+    form: Form,
+    sw_user: SwappyUser<'_>,
+) -> Result<(u64, MessageId), (StatusCode, String)> {
+    use crate::bot::commands::CallbackQueryCommand::{PublishNow, Unschedule};
+
+    let now = ads::now();
+
+    let internal_error = |e: &dyn std::fmt::Display| {
+        log::error!("failed to schedule ad: {}", e.to_string());
+        (StatusCode::INTERNAL_SERVER_ERROR, "Что-то пошло не так, попробуйте позднее".to_string())
+    };
+
+    let group_id = sw_user.group_id;
+    let id = scheduled::next_id(group_id, &app_config.redis_client)
+        .map_err(|e| internal_error(&e))?;
+
+    let text = format!(
+        "Объявление будет опубликовано через {}:\n\n{}",
+        format_duration(publish_at.saturating_sub(now)),
+        form,
+    );
+    let kb = make_signed_kb(vec![vec![
+        ("Опубликовать сейчас 🚀".to_string(), PublishNow(id)),
+        ("Отменить ❌".to_string(), Unschedule(id)),
+    ]], group_id, sw_user.tg_user.id, app_config.callback_key());
+    let report = app_config.bot.send_message(sw_user.tg_user.id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(kb)
+        .await
+        .map_err(|e| internal_error(&e))?;
+
+    let ad = ScheduledAd {
+        id,
+        author: sw_user.tg_user.clone(),
+        publish_at,
+        report_msg_id: Some(report.id),
+        attempts: 0,
+        form,
+    };
+    scheduled::save(group_id, &ad, &app_config.redis_client)
+        .map_err(|e| internal_error(&e))?;
+
+    Ok((id, report.id))
+}
+
+/// Publishes an ad taken out of the queue. The report of the scheduled ad stays
+/// if publishing fails, so the ad can go back to the queue with its controls.
+pub async fn publish_scheduled(
+    app_config: &AppConfig,
+    scheduled_ad: &ScheduledAd,
+    sw_user: &mut SwappyUser<'_>,
+) -> Result<Ad, RequestError> {
+    let ad = Ad::new(sw_user.tg_user.id, MessageId(0), scheduled_ad.form.clone());
+    let ad = publish(app_config, ad, sw_user).await?;
+
+    if let Some(report_id) = scheduled_ad.report_msg_id {
+        if let Err(e) = app_config.bot.delete_message(sw_user.tg_user.id, report_id).await {
+            log::warn!("failed to delete report of scheduled ad: {}", e.to_string());
+        }
+    }
+
+    Ok(ad)
+}
+
+/// Publishes a stored ad again as a new group message, e.g. after it expired.
+pub async fn republish_ad(
+    app_config: &AppConfig,
+    mut ad: Ad,
+    sw_user: &mut SwappyUser<'_>,
+) -> Result<Ad, RequestError> {
+    let old_id = ad.group_msg_id;
+    ad.status = AdStatus::Active;
+
+    let ad = publish(app_config, ad, sw_user).await?;
+    if let Err(e) = ads::remove(sw_user.group_id, old_id, &app_config.redis_client) {
+        log::error!("failed to remove old ad: {}", e.to_string());
+    }
+
+    Ok(ad)
+}
+
+/// Sends the ad to the group as a new message and reports it to the author.
+async fn publish(
+    app_config: &AppConfig,
+    mut ad: Ad,
+    sw_user: &mut SwappyUser<'_>,
+) -> Result<Ad, RequestError> {
+    let group_id = sw_user.group_id;
+
+    let group_msg = post_ad(None, &app_config.bot, &ad.form, sw_user).await?;
+    track_new_ad(app_config, sw_user, group_msg.id).await;
+    ad.group_msg_id = group_msg.id;
+
+    // the ad is out already, so a lost report is not a reason to fail
+    ad.report_msg_id = report_ad(&sw_user.tg_user, group_id, &group_msg, app_config).await
+        .inspect_err(|e| log::error!("failed to report ad: {}", e.to_string()))
+        .ok();
+
+    if let Err(e) = ads::save(group_id, &ad, &app_config.redis_client) {
+        log::error!("failed to save ad: {}", e.to_string());
+    }
+
+    Ok(ad)
+}
+
+async fn check_posting_limits(sw_user: &mut SwappyUser<'_>) -> Result<(), (StatusCode, String)> {
+    match sw_user.check_posting_limits().await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(rejection)) => Err((StatusCode::TOO_MANY_REQUESTS, rejection.to_json())),
+        Err(e) => {
+            log::error!("redis query failed: {}", e.to_string());
+            Err((StatusCode::INTERNAL_SERVER_ERROR,
+                 "Что-то пошло не так, попробуйте позднее".to_string()))
+        }
+    }
+}
+
+async fn reserve_posting_cooldown(sw_user: &mut SwappyUser<'_>) -> Result<(), (StatusCode, String)> {
+    match sw_user.reserve_posting_cooldown().await {
+        Ok(None) => Ok(()),
+        Ok(Some(retry_after)) => Err((StatusCode::TOO_MANY_REQUESTS,
+                                      Rejection::TooSoon { retry_after }.to_json())),
+        Err(e) => {
+            log::error!("failed to reserve posting cooldown: {}", e.to_string());
+            Err((StatusCode::INTERNAL_SERVER_ERROR,
+                 "Что-то пошло не так, попробуйте позднее".to_string()))
+        }
+    }
+}
+
+/// Re-renders user's active ads in the group, so they show the current star count.
+/// An ad that can't be edited, e.g. because it was deleted, doesn't stop the rest.
+pub async fn refresh_ads(app_config: &AppConfig, sw_user: &mut SwappyUser<'_>) {
+    let group_id = sw_user.group_id;
+    let ids = sw_user.ads().await.unwrap_or_else(|e| {
+        log::error!("failed to get ads: {}", e.to_string());
+        vec![]
+    });
+
+    for msg_id in ids {
+        // ads without stored content can't be re-rendered
+        let ad = match ads::load(group_id, msg_id, &app_config.redis_client) {
+            Ok(Some(ad)) if ad.status == AdStatus::Active => ad,
+            Ok(_) => continue,
+            Err(e) => {
+                log::error!("failed to load ad: {}", e.to_string());
+                continue
+            }
+        };
+
+        let text = render_ad(&ad.form, sw_user).await;
+        match app_config.bot.edit_message_text(group_id, msg_id, text)
+            .parse_mode(ParseMode::Html)
+            .await {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+            Err(e) => log::warn!("failed to refresh ad {}: {}", msg_id, e.to_string()),
+        }
+    }
+}
+
+/// Bookkeeping for an ad that has just appeared in the group.
+async fn track_new_ad(app_config: &AppConfig, sw_user: &mut SwappyUser<'_>, msg_id: MessageId) {
+    if let Err(e) = sw_user.set_author(msg_id).await {
+        log::error!("failed to set author: {}", e.to_string());
+    }
+
+    // fresh ads are already at the bottom of the group, no point in bumping them right away
+    if let Err(e) = sw_user.set_repost_cooldown(msg_id).await {
+        log::error!("failed to set repost cooldown: {}", e.to_string());
+    }
+
+    if let Err(e) = ads::mark_published(sw_user.group_id, msg_id, ads::now(), &app_config.redis_client) {
+        log::error!("failed to mark ad published: {}", e.to_string());
+    }
+}
+
 async fn post_ad(
     edit_msg_id: Option<i32>,
     bot: &Bot,
@@ -76,15 +319,7 @@ async fn post_ad(
     bot_user: &mut SwappyUser<'_>,
 ) -> Result<Message, RequestError> {
     let group_id = bot_user.group_id;
-    let sc = bot_user.star_count().await.unwrap_or_default();
-    let stars = if sc > 0 { format!(" (<i>⭐️</i>{})", sc) } else { String::default() };
-
-    let msg = format!(
-        "{}{}:\n\n{}",
-        bot_user,
-        stars,
-        form,
-    );
+    let msg = render_ad(form, bot_user).await;
 
     if let Some(msg_id) = edit_msg_id {
         bot.edit_message_text(group_id, MessageId(msg_id), msg)
@@ -97,37 +332,104 @@ async fn post_ad(
     }
 }
 
+/// Renders the group message of an ad: link to the author with their stars, then the form.
+pub async fn render_ad(form: &Form, bot_user: &mut SwappyUser<'_>) -> String {
+    format!("{}:\n\n{}", render_header(bot_user).await, form)
+}
+
+/// Renders an ad after the deal took place: the form is struck through and marked closed.
+pub async fn render_closed_ad(form: &Form, bot_user: &mut SwappyUser<'_>) -> String {
+    format!("{}:\n\n<s>{}</s>\n\n{}", render_header(bot_user).await, form, CLOSED_MARK)
+}
+
+/// Same as [`render_closed_ad`] for ads without stored content, built from the plain message text.
+pub fn render_closed_text(text: &str) -> String {
+    format!("<s>{}</s>\n\n{}", html::escape(text), CLOSED_MARK)
+}
+
+async fn render_header(bot_user: &mut SwappyUser<'_>) -> String {
+    let sc = bot_user.star_count().await.unwrap_or_default();
+    let dc = bot_user.deal_count().await.unwrap_or_default();
+    let stars = match (sc, dc) {
+        (0, 0) => String::default(),
+        (sc, 0) => format!(" (<i>⭐️</i>{})", sc),
+        (0, dc) => format!(" ({})", format_deals(dc)),
+        (sc, dc) => format!(" (<i>⭐️</i>{}, {})", sc, format_deals(dc)),
+    };
+    let wc = bot_user.warning_count().await.unwrap_or_default();
+    let warnings = if wc > 0 { format!(" <b>{}{}</b>", WARNING_MARK, wc) } else { String::default() };
+
+    format!("{}{}{}", bot_user, stars, warnings)
+}
+
+/// "5 сделок", "2 сделки", "21 сделка".
+pub fn format_deals(count: usize) -> String {
+    let word = match (count % 10, count % 100) {
+        (_, 11..=14) => "сделок",
+        (1, _) => "сделка",
+        (2..=4, _) => "сделки",
+        _ => "сделок",
+    };
+    format!("{} {}", count, word)
+}
+
 async fn report_ad(
     user: &User,
     group_id: ChatId,
     msg: &Message,
-    post_params: PostParams,
     app_config: &AppConfig,
 ) -> Result<MessageId, RequestError> {
-    use crate::bot::commands::CallbackQueryCommand::*;
     let bot = &app_config.bot;
 
+    // ad content is stored server-side, so it can always be edited
+    bot.copy_message(user.id, group_id, msg.id)
+        .reply_markup(make_report_kb(app_config, group_id, msg.id, user.id, true))
+        .await
+}
+
+/// Mini app link which opens the editor for the given ad.
+pub fn make_edit_url(app_config: &AppConfig, group_id: ChatId, msg_id: MessageId) -> Url {
     let mut edit_url = app_config.app_url.clone();
     // edit_url.set_path("/form");
-    let query = format!("edit={}", msg.id.to_string());
+    let query = format!("edit={}&group={}", msg_id, group_id);
     edit_url.set_query(Some(&query));
+    edit_url
+}
+
+/// Builds management keyboard attached to the private copy of an ad.
+pub fn make_report_kb(
+    app_config: &AppConfig,
+    group_id: ChatId,
+    msg_id: MessageId,
+    user_id: UserId,
+    editable: bool,
+) -> InlineKeyboardMarkup {
+    use crate::bot::commands::CallbackQueryCommand::*;
+
+    let edit_url = make_edit_url(app_config, group_id, msg_id);
+    let key = app_config.callback_key();
 
     let mut butts = vec![
         vec![
-            InlineKeyboardButton::callback("Снять 🗑️".to_string(), Delete(msg.id).to_string()),
+            InlineKeyboardButton::callback("Снять 🗑️".to_string(), Delete(msg_id).sign(group_id, user_id, key)),
+        ],
+        vec![
+            InlineKeyboardButton::callback("Поднять ⬆️".to_string(), Repost(msg_id).sign(group_id, user_id, key)),
+        ],
+        vec![
+            InlineKeyboardButton::callback("Сделка состоялась ✅".to_string(), Close(msg_id).sign(group_id, user_id, key)),
         ],
     ];
-    if post_params.keeping {
+    if editable {
         butts[0].insert(0,
             InlineKeyboardButton::web_app("Редактировать ✏️".to_string(), WebAppInfo { url: edit_url }),
         );
     }
 
-    bot.copy_message(user.id, group_id, msg.id)
-        .reply_markup(InlineKeyboardMarkup::new(butts))
-        .await
+    InlineKeyboardMarkup::new(butts)
 }
 
+#[allow(dead_code)]
 fn make_ad_kb(user_id: &UserId) -> InlineKeyboardMarkup {
     let mut kb: Vec<Vec<InlineKeyboardButton>> = vec![];
     let url = format!("tg://user?id={}", user_id).parse().unwrap();
@@ -143,4 +445,18 @@ fn make_ad_kb(user_id: &UserId) -> InlineKeyboardMarkup {
     // kb.push(vec![write, a]);
 
     InlineKeyboardMarkup::new(kb)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_deals;
+
+    #[test]
+    fn deals_are_pluralized() {
+        assert_eq!(format_deals(1), "1 сделка");
+        assert_eq!(format_deals(3), "3 сделки");
+        assert_eq!(format_deals(5), "5 сделок");
+        assert_eq!(format_deals(12), "12 сделок");
+        assert_eq!(format_deals(21), "21 сделка");
+    }
+}
@@ -1,30 +1,316 @@
-use std::error::Error;
-use std::io::Read;
+pub mod ads;
+pub mod badges;
+pub mod complaints;
+pub mod deals;
+pub mod groups;
+pub mod members;
+pub mod migration;
+pub mod roles;
+pub mod scheduled;
+pub mod settings;
+pub mod starred;
+pub mod top;
+
+use std::collections::HashMap;
 use redis::{Commands, RedisError, RedisResult};
 use sha2::{Sha256, Digest};
 use sha2::digest::consts::U32;
 use sha2::digest::generic_array::GenericArray;
 use teloxide::prelude::*;
 
+/// Number of the last one-off migration the database went through.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Runs one-off migrations the database hasn't gone through yet, in the order they were added.
+pub fn migrate(client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let version: Option<u32> = conn.get(SCHEMA_VERSION_KEY)?;
+    let version = version.unwrap_or_default();
+
+    if version < 1 {
+        let marked = ads::backfill_published(client)?;
+        log::info!("marked {} ads published before they could expire", marked);
+        conn.set::<_, _, ()>(SCHEMA_VERSION_KEY, 1)?;
+    }
+
+    if version < 2 {
+        let groups = top::backfill_receivers(client)?;
+        log::info!("indexed star receivers in {} groups", groups);
+        conn.set::<_, _, ()>(SCHEMA_VERSION_KEY, 2)?;
+    }
+
+    if version < 3 {
+        let migrated = migrate_stars(client)?;
+        log::info!("timestamped stars of {} users", migrated);
+        conn.set::<_, _, ()>(SCHEMA_VERSION_KEY, 3)?;
+    }
+
+    if version < 4 {
+        let seeded = seed_joined(client)?;
+        log::info!("counted {} known users as members since before joins were tracked", seeded);
+        conn.set::<_, _, ()>(SCHEMA_VERSION_KEY, 4)?;
+    }
+
+    Ok(())
+}
+
+/// Set once /rekeystars went through every group, so stars are no longer looked up
+/// with the bot token, which salted them before star_salt existed.
+const STARS_REKEYED_KEY: &str = "stars_rekeyed";
+
+pub fn stars_rekeyed(client: &redis::Client) -> RedisResult<bool> {
+    client.get_connection()?.exists(STARS_REKEYED_KEY)
+}
+
+pub fn mark_stars_rekeyed(client: &redis::Client) -> RedisResult<()> {
+    client.get_connection()?.set(STARS_REKEYED_KEY, 1)
+}
+
+/// Month as star windows count it.
+pub const MONTH_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Sorted set of star hashes scored with the time they were given.
+pub fn stars_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:stars", group_id, user_id.0)
+}
+
+/// Earliest time of a star that still counts, given the window in seconds.
+/// Without a window every star counts.
+pub fn window_start(window: Option<u64>) -> u64 {
+    window.map(|window| ads::now().saturating_sub(window)).unwrap_or(0)
+}
+
+/// Stars of the user given within the window.
 pub fn get_star_count(
     user_id: UserId,
     group_id: ChatId,
+    window: Option<u64>,
     client: &redis::Client
 ) -> RedisResult<usize>  {
     let mut conn = client.get_connection()?;
-    conn.scard(&format!("{}:{}:stars", group_id, user_id.0))
+    conn.zcount(stars_key(group_id, user_id), window_start(window), "+inf")
 }
 
+/// Gives a star hashed with the first of `salts`. Returns false if the giver already gave one,
+/// in which case a star hashed with one of the legacy salts is re-keyed on the way.
+/// Giving a star again renews it, so it stays within the window longer.
 pub fn give_star(
     giver: UserId,
     receiver: UserId,
-    salt: &[u8],
+    group_id: ChatId,
+    salts: &[Vec<u8>],
+    redis_client: &redis::Client,
+) -> Result<bool, RedisError> {
+    let mut conn = redis_client.get_connection()?;
+    let redis_key = stars_key(group_id, receiver);
+    let renewed = rekey_star(&mut conn, giver, receiver, salts, &redis_key)?;
+
+    let (added, (), ()): (usize, (), ()) = redis::pipe()
+        .zadd(&redis_key, &hash(giver, receiver, &salts[0])[..], ads::now())
+        .sadd(top::receivers_key(group_id), receiver.0)
+        .sadd(givers_key(group_id), giver.0)
+        .query(&mut conn)?;
+    Ok(added > 0 && !renewed)
+}
+
+/// Takes back a star given earlier, whatever salt it was hashed with.
+/// Returns false if there was no such star.
+pub fn take_star(
+    giver: UserId,
+    receiver: UserId,
+    salts: &[Vec<u8>],
     redis_key: &str,
     redis_client: &redis::Client,
-) -> Result<(), RedisError> {
+) -> Result<bool, RedisError> {
     let mut conn = redis_client.get_connection()?;
-    conn.sadd(redis_key, &hash(giver, receiver, salt)[..])?;
-    Ok(())
+    let hashes: Vec<_> = salts.iter().map(|salt| hash(giver, receiver, salt).to_vec()).collect();
+    let removed: usize = conn.zrem(redis_key, hashes)?;
+    Ok(removed > 0)
+}
+
+/// Replaces the star hashed with a legacy salt, if there is one, with the current hash.
+/// The star keeps the time it was given.
+fn rekey_star(
+    conn: &mut redis::Connection,
+    giver: UserId,
+    receiver: UserId,
+    salts: &[Vec<u8>],
+    redis_key: &str,
+) -> RedisResult<bool> {
+    for salt in &salts[1..] {
+        let legacy = hash(giver, receiver, salt);
+        let given_at: Option<u64> = conn.zscore(redis_key, &legacy[..])?;
+        if let Some(given_at) = given_at {
+            redis::pipe()
+                .atomic()
+                .zrem(redis_key, &legacy[..])
+                .zadd(redis_key, &hash(giver, receiver, &salts[0])[..], given_at)
+                .query::<()>(conn)?;
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Outcome of [`rekey_stars`].
+#[derive(Debug, Default)]
+pub struct RekeyReport {
+    pub rekeyed: usize,
+    /// Stars given by someone the bot knows nothing about
+    pub unrecognized: usize,
+}
+
+/// Re-keys stars hashed with legacy salts. Hashes can't be inverted, so every user the bot knows
+/// of is tried as a giver. Stars of unknown givers keep being re-keyed one by one by [`give_star`].
+///
+/// Stars of a receiver are fetched at once and matched locally, so it takes a couple of requests
+/// per receiver whatever the number of givers.
+pub fn rekey_stars(
+    group_id: ChatId,
+    salts: &[Vec<u8>],
+    client: &redis::Client,
+) -> RedisResult<RekeyReport> {
+    let mut conn = client.get_connection()?;
+    let givers = known_users(group_id, client)?;
+    let receivers: Vec<u64> = conn.smembers(top::receivers_key(group_id))?;
+
+    let mut report = RekeyReport::default();
+    for receiver in receivers.into_iter().map(UserId) {
+        let key = stars_key(group_id, receiver);
+        let stars: Vec<(Vec<u8>, u64)> = conn.zrange_withscores(&key, 0, -1)?;
+        let stars: HashMap<_, _> = stars.into_iter().collect();
+        let mut unrecognized = stars.len();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        let mut rekeyed = 0;
+        for giver in &givers {
+            if unrecognized == 0 { break }
+            if *giver == receiver { continue }
+
+            let current = hash(*giver, receiver, &salts[0]);
+            if stars.contains_key(&current[..]) {
+                unrecognized -= 1;
+                continue;
+            }
+
+            let legacy = salts[1..].iter()
+                .map(|salt| hash(*giver, receiver, salt))
+                .find_map(|legacy| stars.get(&legacy[..]).map(|given_at| (legacy, *given_at)));
+            if let Some((legacy, given_at)) = legacy {
+                // the star keeps the time it was given
+                pipe.zrem(&key, &legacy[..]).zadd(&key, &current[..], given_at);
+                rekeyed += 1;
+                unrecognized -= 1;
+            }
+        }
+
+        if rekeyed > 0 { pipe.query::<()>(&mut conn)?; }
+        report.rekeyed += rekeyed;
+        report.unrecognized += unrecognized;
+    }
+
+    Ok(report)
+}
+
+/// Turns star sets left from before stars were timestamped into sorted sets.
+/// The time those stars were given is unknown, so they count as given now.
+/// Returns the number of converted sets.
+fn migrate_stars(client: &redis::Client) -> RedisResult<usize> {
+    let mut conn = client.get_connection()?;
+    let keys: Vec<String> = conn.scan_match("*:stars")?.collect();
+    let now = ads::now();
+
+    let mut migrated = 0;
+    for key in keys {
+        let kind: String = redis::cmd("TYPE").arg(&key).query(&mut conn)?;
+        if kind != "set" { continue }
+
+        let hashes: Vec<Vec<u8>> = conn.smembers(&key)?;
+        let scored: Vec<_> = hashes.iter().map(|hash| (now, &hash[..])).collect();
+        redis::pipe()
+            .atomic()
+            .del(&key)
+            .zadd_multiple(&key, &scored)
+            .query::<()>(&mut conn)?;
+        migrated += 1;
+    }
+
+    Ok(migrated)
+}
+
+/// Lets users the bot knew of before it tracked joins give stars right away,
+/// instead of waiting for the minimum membership from the first time they're asked about.
+fn seed_joined(client: &redis::Client) -> RedisResult<usize> {
+    let mut seeded = 0;
+    for group_id in groups::load(client)? {
+        let users = known_users(group_id, client)?;
+        seeded += members::set_joined_long_ago(group_id, &users, client)?;
+    }
+
+    Ok(seeded)
+}
+
+/// Set of users who have ever given a star in the group.
+fn givers_key(group_id: ChatId) -> String {
+    format!("{}:givers", group_id)
+}
+
+/// Kinds of keys named `{group}:{user}:{kind}`. Other keys have ids of messages and such there.
+const USER_KEYS: [&str; 10] = [
+    "stars", "ads", "scheduled", "last_post", "given", "badges_refreshed", "complaint_target", "warnings", "deals",
+    "deal_proposals",
+];
+
+/// Ids of users the bot knows of in the group: those it saw joining, giving or getting stars,
+/// and those who have ads or anything else stored under their id.
+pub fn known_users(group_id: ChatId, client: &redis::Client) -> RedisResult<Vec<UserId>> {
+    let mut conn = client.get_connection()?;
+    let mut users: Vec<u64> = conn.sunion(&[top::receivers_key(group_id), givers_key(group_id)])?;
+    users.extend(members::joined(group_id, client)?.into_iter().map(|user_id| user_id.0));
+
+    let prefix = format!("{}:", group_id);
+    let keys: Vec<String> = conn.scan_match(format!("{}*:*", prefix))?.collect();
+    users.extend(keys.iter().filter_map(|key| {
+        let (id, kind) = key.strip_prefix(&prefix)?.split_once(':')?;
+        let kind = kind.split(':').next()?;
+        if USER_KEYS.contains(&kind) { id.parse::<u64>().ok() } else { None }
+    }));
+
+    users.sort();
+    users.dedup();
+    Ok(users.into_iter().map(UserId).collect())
+}
+
+fn star_notifications_off_key(group_id: ChatId) -> String {
+    format!("{}:star_notifications_off", group_id)
+}
+
+/// Users get a message about every new star unless they turned it off.
+pub fn star_notifications_enabled(
+    user_id: UserId,
+    group_id: ChatId,
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let off: bool = conn.sismember(star_notifications_off_key(group_id), user_id.0)?;
+    Ok(!off)
+}
+
+/// Turns notifications about new stars on or off. Returns whether they are on now.
+pub fn toggle_star_notifications(
+    user_id: UserId,
+    group_id: ChatId,
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let key = star_notifications_off_key(group_id);
+    let removed: usize = conn.srem(&key, user_id.0)?;
+    if removed == 0 {
+        conn.sadd::<_, _, ()>(&key, user_id.0)?;
+    }
+    Ok(removed > 0)
 }
 
 fn hash(
@@ -42,7 +328,7 @@ fn hash(
 #[cfg(test)]
 mod tests {
     use teloxide::prelude::*;
-    use crate::store::hash;
+    use crate::store::{ads, hash, window_start};
 
     #[test]
     fn hash_is_same() {
@@ -59,4 +345,12 @@ mod tests {
         println!("{:?}", hash1);
         assert_eq!(hash1, hash2)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn window_start_counts_back_from_now() {
+        assert_eq!(window_start(None), 0);
+
+        let start = window_start(Some(60));
+        assert!(start + 60 >= ads::now() - 1 && start + 60 <= ads::now());
+    }
+}
@@ -0,0 +1,295 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use redis::{Commands, RedisResult};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use crate::site::form::Form;
+
+/// Published ad as the bot remembers it.
+///
+/// Ads published before the content was stored server-side have no record at all,
+/// so every lookup may come back empty.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ad {
+    pub author: UserId,
+    pub group_msg_id: MessageId,
+    pub report_msg_id: Option<MessageId>,
+    /// Unix time of the first publication
+    pub created_at: u64,
+    /// Unix time of the last edit
+    pub edited_at: Option<u64>,
+    #[serde(default)]
+    pub status: AdStatus,
+    pub form: Form,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum AdStatus {
+    #[default]
+    Active,
+    /// Removed from the group after its lifetime ended, can be renewed by the author
+    Expired,
+    /// The deal took place, the ad stays in the group struck through
+    Closed,
+}
+
+impl Ad {
+    pub fn new(author: UserId, group_msg_id: MessageId, form: Form) -> Self {
+        Ad {
+            author,
+            group_msg_id,
+            report_msg_id: None,
+            created_at: now(),
+            edited_at: None,
+            status: AdStatus::Active,
+            form,
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.edited_at = Some(now());
+    }
+}
+
+fn ad_key(group_id: ChatId, msg_id: MessageId) -> String {
+    format!("{}:ad:{}", group_id, msg_id.0)
+}
+
+/// Set of group message ids of user's active ads.
+pub fn authored_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:ads", group_id, user_id.0)
+}
+
+fn published_key(group_id: ChatId) -> String {
+    format!("{}:published", group_id)
+}
+
+/// Hash of group message id to the author, for ads that have no record and aren't in the author's set
+/// of active ads, or will be taken out of it when they expire.
+fn legacy_authors_key(group_id: ChatId) -> String {
+    format!("{}:legacy_authors", group_id)
+}
+
+pub fn save(
+    group_id: ChatId,
+    ad: &Ad,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let json = serde_json::to_string(ad).expect("ad should be serializable");
+    conn.set(ad_key(group_id, ad.group_msg_id), json)
+}
+
+pub fn load(
+    group_id: ChatId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<Option<Ad>> {
+    let mut conn = client.get_connection()?;
+    let json: Option<String> = conn.get(ad_key(group_id, msg_id))?;
+
+    Ok(json.and_then(|json| {
+        serde_json::from_str(&json)
+            .inspect_err(|e| log::error!("broken ad record {}: {}", msg_id, e))
+            .ok()
+    }))
+}
+
+pub fn remove(
+    group_id: ChatId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.del(ad_key(group_id, msg_id))
+}
+
+/// Moves the record of a reposted ad to its new group message id.
+/// Publication time is moved along with it.
+///
+/// Returns false if there was nothing to move.
+pub fn rename(
+    group_id: ChatId,
+    old_id: MessageId,
+    new_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let published_at: Option<u64> = conn.zscore(published_key(group_id), old_id.0)?;
+    if let Some(published_at) = published_at {
+        unmark_published(group_id, old_id, client)?;
+        mark_published(group_id, new_id, published_at, client)?;
+    }
+    if let Some(author) = take_legacy_author(group_id, old_id, client)? {
+        conn.hset::<_, _, _, ()>(legacy_authors_key(group_id), new_id.0, author.0)?;
+    }
+
+    if let Some(mut ad) = load(group_id, old_id, client)? {
+        ad.group_msg_id = new_id;
+        save(group_id, &ad, client)?;
+        remove(group_id, old_id, client)?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+pub fn unset_author(
+    group_id: ChatId,
+    user_id: UserId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.srem(authored_key(group_id, user_id), msg_id.0)
+}
+
+/// Drops everything known about the ad, e.g. after it was deleted from the group.
+pub fn forget(
+    group_id: ChatId,
+    user_id: UserId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    unset_author(group_id, user_id, msg_id, client)?;
+    unmark_published(group_id, msg_id, client)?;
+    take_legacy_author(group_id, msg_id, client)?;
+    remove(group_id, msg_id, client)
+}
+
+/// Remembers the author of an ad that has no record, e.g. once it's not active anymore.
+pub fn set_legacy_author(
+    group_id: ChatId,
+    user_id: UserId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.hset(legacy_authors_key(group_id), msg_id.0, user_id.0)
+}
+
+pub fn legacy_author(
+    group_id: ChatId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<Option<UserId>> {
+    let mut conn = client.get_connection()?;
+    let author: Option<u64> = conn.hget(legacy_authors_key(group_id), msg_id.0)?;
+    Ok(author.map(UserId))
+}
+
+/// Author of an ad that has no record, forgetting it.
+pub fn take_legacy_author(
+    group_id: ChatId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<Option<UserId>> {
+    let mut conn = client.get_connection()?;
+    let key = legacy_authors_key(group_id);
+    let (author, ()): (Option<u64>, ()) = redis::pipe()
+        .atomic()
+        .hget(&key, msg_id.0)
+        .hdel(&key, msg_id.0)
+        .query(&mut conn)?;
+    Ok(author.map(UserId))
+}
+
+/// Remembers when the ad got into the group, so it can expire later.
+pub fn mark_published(
+    group_id: ChatId,
+    msg_id: MessageId,
+    published_at: u64,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.zadd(published_key(group_id), msg_id.0, published_at)
+}
+
+pub fn unmark_published(
+    group_id: ChatId,
+    msg_id: MessageId,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.zrem(published_key(group_id), msg_id.0)
+}
+
+/// Ads published at or before the given unix time.
+pub fn published_before(
+    group_id: ChatId,
+    before: u64,
+    client: &redis::Client,
+) -> RedisResult<Vec<MessageId>> {
+    let mut conn = client.get_connection()?;
+    let ids: Vec<i32> = conn.zrangebyscore(published_key(group_id), "-inf", before)?;
+    Ok(ids.into_iter().map(MessageId).collect())
+}
+
+/// Marks active ads published before ads could expire, so they expire too.
+/// They count as published when their record was created, or now if they have no record,
+/// in which case their author is remembered for when they expire.
+/// Returns the number of marked ads.
+pub fn backfill_published(client: &redis::Client) -> RedisResult<usize> {
+    let mut conn = client.get_connection()?;
+    let keys: Vec<String> = conn.scan_match("*:*:ads")?.collect();
+    let now = now();
+
+    let mut marked = 0;
+    for key in keys {
+        let mut parts = key.split(':');
+        let (Some(group_id), Some(author)) = (parts.next(), parts.next()) else { continue };
+        let (Ok(group_id), Ok(author)) = (group_id.parse().map(ChatId), author.parse::<u64>()) else { continue };
+
+        let ids: Vec<i32> = conn.smembers(&key)?;
+        for id in ids {
+            let published_at = match load(group_id, MessageId(id), client)? {
+                Some(ad) => ad.created_at,
+                None => {
+                    conn.hset::<_, _, _, ()>(legacy_authors_key(group_id), id, author)?;
+                    now
+                }
+            };
+            let added: usize = redis::cmd("ZADD")
+                .arg(published_key(group_id))
+                .arg("NX")
+                .arg(published_at)
+                .arg(id)
+                .query(&mut conn)?;
+            marked += added;
+        }
+    }
+
+    Ok(marked)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::prelude::*;
+    use teloxide::types::MessageId;
+    use crate::site::form::Form;
+    use super::Ad;
+
+    const FORM: &str = r#"{"buyOrSell":"Продать","sellingCurr":"EUR","buyingCurr":"RUB","sum":"500",
+        "inParts":true,"cb":false,"rate":"100","euMethods":["bizum"],"ruMethods":["sber"],
+        "euMethodsStr":"","ruMethodsStr":"","euMore":false,"ruMore":false,"comment":"",
+        "cash":false,"cashOnly":false,"location":""}"#;
+
+    #[test]
+    fn ad_survives_roundtrip() {
+        let form: Form = serde_json::from_str(FORM).unwrap();
+        let mut ad = Ad::new(UserId(113472905), MessageId(42), form);
+        ad.report_msg_id = Some(MessageId(7));
+
+        let json = serde_json::to_string(&ad).unwrap();
+        let restored: Ad = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.author, ad.author);
+        assert_eq!(restored.group_msg_id, ad.group_msg_id);
+        assert_eq!(restored.report_msg_id, ad.report_msg_id);
+        assert_eq!(restored.form.to_string(), ad.form.to_string());
+    }
+}
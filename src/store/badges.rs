@@ -0,0 +1,65 @@
+use redis::{Commands, RedisResult};
+use teloxide::prelude::*;
+
+fn throttle_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:badges_refreshed", group_id, user_id.0)
+}
+
+fn pending_key(group_id: ChatId) -> String {
+    format!("{}:badges_pending", group_id)
+}
+
+/// Takes the right to re-render user's ads now. At most once per `interval` seconds.
+///
+/// If it's too soon, the user is queued and handed out by [`take_pending`] later.
+pub fn try_refresh(
+    group_id: ChatId,
+    user_id: UserId,
+    interval: u64,
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    if acquire(&mut conn, group_id, user_id, interval)? {
+        return Ok(true);
+    }
+
+    conn.sadd::<_, _, ()>(pending_key(group_id), user_id.0)?;
+    Ok(false)
+}
+
+/// Queued users whose ads can be re-rendered now.
+pub fn take_pending(
+    group_id: ChatId,
+    interval: u64,
+    client: &redis::Client,
+) -> RedisResult<Vec<UserId>> {
+    let mut conn = client.get_connection()?;
+    let pending: Vec<u64> = conn.smembers(pending_key(group_id))?;
+
+    let mut ready = vec![];
+    for user_id in pending.into_iter().map(UserId) {
+        if acquire(&mut conn, group_id, user_id, interval)? {
+            conn.srem::<_, _, ()>(pending_key(group_id), user_id.0)?;
+            ready.push(user_id);
+        }
+    }
+
+    Ok(ready)
+}
+
+fn acquire(
+    conn: &mut redis::Connection,
+    group_id: ChatId,
+    user_id: UserId,
+    interval: u64,
+) -> RedisResult<bool> {
+    let res: Option<String> = redis::cmd("SET")
+        .arg(throttle_key(group_id, user_id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(interval)
+        .query(conn)?;
+
+    Ok(res.is_some())
+}
@@ -0,0 +1,148 @@
+use redis::{Commands, RedisResult};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use crate::store::ads::now;
+
+/// Complaint of one member about another, reviewed by maintainers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Complaint {
+    pub id: u64,
+    pub complainer: UserId,
+    pub target: UserId,
+    pub reason: String,
+    /// Unix time the complaint was filed
+    pub created_at: u64,
+    #[serde(default)]
+    pub status: ComplaintStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ComplaintStatus {
+    #[default]
+    Pending,
+    /// Shows up as a warning next to the target's name
+    Approved,
+    Rejected,
+}
+
+fn complaint_key(group_id: ChatId, id: u64) -> String {
+    format!("{}:complaint:{}", group_id, id)
+}
+
+fn next_id_key(group_id: ChatId) -> String {
+    format!("{}:complaint:next_id", group_id)
+}
+
+/// Target of a complaint whose reason the complainer hasn't sent yet.
+fn pending_key(group_id: ChatId, complainer: UserId) -> String {
+    format!("{}:{}:complaint_target", group_id, complainer.0)
+}
+
+/// Set of ids of approved complaints about the user.
+pub fn warnings_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:warnings", group_id, user_id.0)
+}
+
+/// Remembers whom the user complains about until they send the reason.
+pub fn start(
+    group_id: ChatId,
+    complainer: UserId,
+    target: UserId,
+    timeout: u64,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.set_ex(pending_key(group_id, complainer), target.0, timeout)
+}
+
+/// Files the complaint the user started, if they did. Returns `None` otherwise.
+pub fn file(
+    group_id: ChatId,
+    complainer: UserId,
+    reason: String,
+    client: &redis::Client,
+) -> RedisResult<Option<Complaint>> {
+    let mut conn = client.get_connection()?;
+    let target: Option<u64> = conn.get_del(pending_key(group_id, complainer))?;
+    let Some(target) = target else { return Ok(None) };
+
+    let complaint = Complaint {
+        id: conn.incr(next_id_key(group_id), 1)?,
+        complainer,
+        target: UserId(target),
+        reason,
+        created_at: now(),
+        status: ComplaintStatus::Pending,
+    };
+    save(group_id, &complaint, client)?;
+
+    Ok(Some(complaint))
+}
+
+pub fn save(
+    group_id: ChatId,
+    complaint: &Complaint,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let json = serde_json::to_string(complaint).expect("complaint should be serializable");
+    conn.set(complaint_key(group_id, complaint.id), json)
+}
+
+pub fn load(
+    group_id: ChatId,
+    id: u64,
+    client: &redis::Client,
+) -> RedisResult<Option<Complaint>> {
+    let mut conn = client.get_connection()?;
+    let json: Option<String> = conn.get(complaint_key(group_id, id))?;
+    Ok(json.and_then(|json| parse(id, &json)))
+}
+
+fn parse(id: u64, json: &str) -> Option<Complaint> {
+    serde_json::from_str(json)
+        .inspect_err(|e| log::error!("broken complaint {}: {}", id, e))
+        .ok()
+}
+
+/// Settles a pending complaint. Approved ones become warnings of the target.
+/// Moderators pressing the buttons at once settle it once.
+///
+/// Returns `None` if there is no such complaint or it was already settled.
+pub fn resolve(
+    group_id: ChatId,
+    id: u64,
+    approve: bool,
+    client: &redis::Client,
+) -> RedisResult<Option<Complaint>> {
+    let mut conn = client.get_connection()?;
+    let key = complaint_key(group_id, id);
+
+    redis::transaction(&mut conn, &[&key], |conn, pipe| {
+        let json: Option<String> = conn.get(&key)?;
+        let complaint = json.and_then(|json| parse(id, &json));
+        let Some(mut complaint) = complaint.filter(|c| c.status == ComplaintStatus::Pending) else {
+            return Ok(Some(None));
+        };
+
+        complaint.status = if approve { ComplaintStatus::Approved } else { ComplaintStatus::Rejected };
+        let json = serde_json::to_string(&complaint).expect("complaint should be serializable");
+        pipe.set(&key, json).ignore();
+        if approve {
+            pipe.sadd(warnings_key(group_id, complaint.target), complaint.id).ignore();
+        }
+
+        // nothing is returned if the complaint changed meanwhile, then it's tried again
+        let settled: Option<()> = pipe.query(conn)?;
+        Ok(settled.map(|_| Some(complaint)))
+    })
+}
+
+pub fn get_warning_count(
+    user_id: UserId,
+    group_id: ChatId,
+    client: &redis::Client,
+) -> RedisResult<usize> {
+    let mut conn = client.get_connection()?;
+    conn.scard(warnings_key(group_id, user_id))
+}
@@ -0,0 +1,164 @@
+use redis::{Commands, RedisResult};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use crate::store::ads::now;
+
+/// Deal between two members, recorded once both of them agree it took place.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Deal {
+    pub id: u64,
+    pub proposer: UserId,
+    /// The one who has to confirm the deal
+    pub counterparty: UserId,
+    /// Unix time the deal was proposed
+    pub created_at: u64,
+    #[serde(default)]
+    pub status: DealStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum DealStatus {
+    #[default]
+    Proposed,
+    Confirmed,
+    Declined,
+}
+
+fn deal_key(group_id: ChatId, id: u64) -> String {
+    format!("{}:deal:{}", group_id, id)
+}
+
+fn next_id_key(group_id: ChatId) -> String {
+    format!("{}:deal:next_id", group_id)
+}
+
+/// Set of ids of users the user had confirmed deals with,
+/// so a pair of members can't inflate their counts with deals with each other.
+pub fn confirmed_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:deals", group_id, user_id.0)
+}
+
+/// Number of deals the user proposed lately, see [`propose`].
+fn proposals_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:deal_proposals", group_id, user_id.0)
+}
+
+/// Why a deal can't be proposed.
+#[derive(Debug)]
+pub enum ProposalError {
+    /// The deal with the counterparty is counted already
+    Confirmed,
+    /// The proposer made too many proposals lately
+    TooMany,
+    Redis(redis::RedisError),
+}
+
+impl From<redis::RedisError> for ProposalError {
+    fn from(e: redis::RedisError) -> Self {
+        ProposalError::Redis(e)
+    }
+}
+
+/// Records a deal for the counterparty to confirm.
+/// The proposer can make up to `max_proposals` of them within `period` seconds.
+pub fn propose(
+    group_id: ChatId,
+    proposer: UserId,
+    counterparty: UserId,
+    max_proposals: usize,
+    period: u64,
+    client: &redis::Client,
+) -> Result<Deal, ProposalError> {
+    let mut conn = client.get_connection()?;
+    let confirmed: bool = conn.sismember(confirmed_key(group_id, proposer), counterparty.0)?;
+    if confirmed { return Err(ProposalError::Confirmed) }
+
+    // the period starts with the first proposal
+    let key = proposals_key(group_id, proposer);
+    let (proposals,): (usize,) = redis::pipe()
+        .atomic()
+        .cmd("SET").arg(&key).arg(0).arg("NX").arg("EX").arg(period).ignore()
+        .incr(&key, 1)
+        .query(&mut conn)?;
+    if proposals > max_proposals { return Err(ProposalError::TooMany) }
+
+    let deal = Deal {
+        id: conn.incr(next_id_key(group_id), 1)?,
+        proposer,
+        counterparty,
+        created_at: now(),
+        status: DealStatus::Proposed,
+    };
+    save(group_id, &deal, client)?;
+
+    Ok(deal)
+}
+
+pub fn save(
+    group_id: ChatId,
+    deal: &Deal,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let json = serde_json::to_string(deal).expect("deal should be serializable");
+    conn.set(deal_key(group_id, deal.id), json)
+}
+
+pub fn load(
+    group_id: ChatId,
+    id: u64,
+    client: &redis::Client,
+) -> RedisResult<Option<Deal>> {
+    let mut conn = client.get_connection()?;
+    let json: Option<String> = conn.get(deal_key(group_id, id))?;
+    Ok(json.and_then(|json| parse(id, &json)))
+}
+
+fn parse(id: u64, json: &str) -> Option<Deal> {
+    serde_json::from_str(json)
+        .inspect_err(|e| log::error!("broken deal {}: {}", id, e))
+        .ok()
+}
+
+/// Settles a proposed deal. Confirmed ones count for both parties, once per pair of them.
+/// Pressing the buttons more than once settles it once.
+///
+/// Returns `None` if there is no such deal or it was already settled.
+pub fn resolve(
+    group_id: ChatId,
+    id: u64,
+    confirm: bool,
+    client: &redis::Client,
+) -> RedisResult<Option<Deal>> {
+    let mut conn = client.get_connection()?;
+    let key = deal_key(group_id, id);
+
+    redis::transaction(&mut conn, &[&key], |conn, pipe| {
+        let json: Option<String> = conn.get(&key)?;
+        let deal = json.and_then(|json| parse(id, &json));
+        let Some(mut deal) = deal.filter(|deal| deal.status == DealStatus::Proposed) else {
+            return Ok(Some(None));
+        };
+
+        deal.status = if confirm { DealStatus::Confirmed } else { DealStatus::Declined };
+        let json = serde_json::to_string(&deal).expect("deal should be serializable");
+        pipe.set(&key, json).ignore();
+        if confirm {
+            pipe.sadd(confirmed_key(group_id, deal.proposer), deal.counterparty.0).ignore()
+                .sadd(confirmed_key(group_id, deal.counterparty), deal.proposer.0).ignore();
+        }
+
+        // nothing is returned if the deal changed meanwhile, then it's tried again
+        let settled: Option<()> = pipe.query(conn)?;
+        Ok(settled.map(|_| Some(deal)))
+    })
+}
+
+pub fn get_deal_count(
+    user_id: UserId,
+    group_id: ChatId,
+    client: &redis::Client,
+) -> RedisResult<usize> {
+    let mut conn = client.get_connection()?;
+    conn.scard(confirmed_key(group_id, user_id))
+}
@@ -0,0 +1,50 @@
+//! Groups the bot serves and the group each user works with in private chat.
+
+use redis::{Commands, RedisResult};
+use teloxide::prelude::*;
+use crate::bot::TARGET_GROUP_ID_KEY;
+
+/// List of registered groups, the default one first.
+const GROUPS_KEY: &str = "target_groups";
+
+fn selected_key(user_id: UserId) -> String {
+    format!("user:{}:group", user_id.0)
+}
+
+/// Registered groups. Before there could be several of them, the only one was kept on its own.
+pub fn load(client: &redis::Client) -> RedisResult<Vec<ChatId>> {
+    let mut conn = client.get_connection()?;
+    let groups: Vec<i64> = conn.lrange(GROUPS_KEY, 0, -1)?;
+    if !groups.is_empty() {
+        return Ok(groups.into_iter().map(ChatId).collect());
+    }
+
+    let legacy: Option<i64> = conn.get(TARGET_GROUP_ID_KEY)?;
+    Ok(legacy.filter(|id| *id != 0).map(ChatId).into_iter().collect())
+}
+
+pub fn save(groups: &[ChatId], client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let ids: Vec<i64> = groups.iter().map(|group| group.0).collect();
+
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(GROUPS_KEY);
+    if let Some(default) = ids.first() {
+        pipe.rpush(GROUPS_KEY, &ids)
+            // older versions read just the default group
+            .set(TARGET_GROUP_ID_KEY, default);
+    }
+    pipe.query(&mut conn)
+}
+
+/// Makes the user work with the group in private chat with the bot.
+pub fn select(user_id: UserId, group_id: ChatId, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.set(selected_key(user_id), group_id.0)
+}
+
+pub fn selected(user_id: UserId, client: &redis::Client) -> RedisResult<Option<ChatId>> {
+    let mut conn = client.get_connection()?;
+    let group_id: Option<i64> = conn.get(selected_key(user_id))?;
+    Ok(group_id.map(ChatId))
+}
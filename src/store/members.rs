@@ -0,0 +1,89 @@
+use redis::{Commands, RedisResult};
+use teloxide::prelude::*;
+
+/// Hash of user id to the unix time they joined the group.
+/// Only joins seen by the bot are there.
+fn joined_key(group_id: ChatId) -> String {
+    format!("{}:joined", group_id)
+}
+
+fn given_key(group_id: ChatId, user_id: UserId, day: u64) -> String {
+    format!("{}:{}:given:{}", group_id, user_id.0, day)
+}
+
+pub fn set_joined(group_id: ChatId, user_id: UserId, at: u64, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.hset(joined_key(group_id), user_id.0, at)
+}
+
+pub fn forget_joined(group_id: ChatId, user_id: UserId, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.hdel(joined_key(group_id), user_id.0)
+}
+
+/// Counts the users as members since long ago, unless the bot saw them joining.
+/// Returns the number of users that weren't counted before.
+pub fn set_joined_long_ago(group_id: ChatId, user_ids: &[UserId], client: &redis::Client) -> RedisResult<usize> {
+    if user_ids.is_empty() { return Ok(0) }
+
+    let mut conn = client.get_connection()?;
+    let key = joined_key(group_id);
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for user_id in user_ids {
+        pipe.hset_nx(&key, user_id.0, 0);
+    }
+    let added: Vec<usize> = pipe.query(&mut conn)?;
+    Ok(added.into_iter().sum())
+}
+
+/// Users the bot saw joining the group.
+pub fn joined(group_id: ChatId, client: &redis::Client) -> RedisResult<Vec<UserId>> {
+    let mut conn = client.get_connection()?;
+    let user_ids: Vec<u64> = conn.hkeys(joined_key(group_id))?;
+    Ok(user_ids.into_iter().map(UserId).collect())
+}
+
+/// When the user joined the group. Users known from before joins were tracked count as old
+/// members. Otherwise, if the bot didn't see the join, the first time it's asked counts as one,
+/// so accounts the bot has never heard of can't skip the wait.
+pub fn joined_at(group_id: ChatId, user_id: UserId, now: u64, client: &redis::Client) -> RedisResult<u64> {
+    let mut conn = client.get_connection()?;
+    let key = joined_key(group_id);
+    let ((), joined_at): ((), u64) = redis::pipe()
+        .atomic()
+        .hset_nx(&key, user_id.0, now)
+        .hget(&key, user_id.0)
+        .query(&mut conn)?;
+    Ok(joined_at)
+}
+
+/// Counts a new star the user is about to give on the given day, counted from the unix epoch,
+/// unless `within_budget` refuses the resulting count. Returns whether the star got counted.
+///
+/// Counting comes first, so stars given at once can't all see the same budget left.
+pub fn reserve_star(
+    group_id: ChatId,
+    user_id: UserId,
+    day: u64,
+    within_budget: impl Fn(usize) -> bool,
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let key = given_key(group_id, user_id, day);
+    let (given, ()): (usize, ()) = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .expire(&key, 2 * 24 * 60 * 60)
+        .query(&mut conn)?;
+
+    if within_budget(given) { return Ok(true) }
+    release_star(group_id, user_id, day, client)?;
+    Ok(false)
+}
+
+/// Takes back a star counted with [`reserve_star`] that wasn't given after all.
+pub fn release_star(group_id: ChatId, user_id: UserId, day: u64, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.decr(given_key(group_id, user_id, day), 1)
+}
@@ -0,0 +1,205 @@
+//! Moving data to a new group id, since every key is prefixed with the group.
+//!
+//! Data is merged into whatever the new group already has, nothing is removed from the old one.
+
+use std::collections::HashMap;
+use redis::{Commands, Connection, RedisResult};
+use teloxide::prelude::*;
+
+/// What a migration did, or would do on a dry run.
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// Users whose stars were found in the old group
+    pub starred_users: usize,
+    pub stars: usize,
+    /// Stars the new group doesn't have yet
+    pub new_stars: usize,
+    /// Giver-side star indexes
+    pub starred_indexes: usize,
+    pub complaints: usize,
+    /// Complaints not copied to the new group by earlier runs
+    pub new_complaints: usize,
+    /// Users with approved complaints about them
+    pub warned_users: usize,
+    pub deals: usize,
+    /// Deals not copied to the new group by earlier runs
+    pub new_deals: usize,
+    pub ads: usize,
+    /// Ad records the new group doesn't have yet
+    pub new_ads: usize,
+}
+
+/// Counts what [`migrate`] would move, without changing anything.
+pub fn plan(from: ChatId, to: ChatId, with_ads: bool, client: &redis::Client) -> RedisResult<MigrationReport> {
+    run(from, to, with_ads, true, client)
+}
+
+/// Merges stars, complaints, deals, settings and, if asked, ads metadata of the old group into the new one.
+pub fn migrate(from: ChatId, to: ChatId, with_ads: bool, client: &redis::Client) -> RedisResult<MigrationReport> {
+    run(from, to, with_ads, false, client)
+}
+
+fn run(
+    from: ChatId,
+    to: ChatId,
+    with_ads: bool,
+    dry_run: bool,
+    client: &redis::Client,
+) -> RedisResult<MigrationReport> {
+    let mut conn = client.get_connection()?;
+    let mut report = MigrationReport::default();
+
+    for (src, dst) in matching_keys(&mut conn, from, to, "*:stars")? {
+        let hashes: Vec<Vec<u8>> = conn.zrange(&src, 0, -1)?;
+        report.starred_users += 1;
+        report.stars += hashes.len();
+        for hash in &hashes {
+            let score: Option<u64> = conn.zscore(&dst, hash)?;
+            if score.is_none() { report.new_stars += 1; }
+        }
+
+        // the same giver and receiver make the same hash, the earliest star wins
+        if !dry_run { conn.zunionstore_min::<_, _, ()>(&dst, &[&dst, &src])?; }
+    }
+
+    for (src, dst) in matching_keys(&mut conn, from, to, "starred:*")? {
+        report.starred_indexes += 1;
+        if !dry_run { merge_hash(&mut conn, &src, &dst)?; }
+    }
+
+    for suffix in ["star_notifications_off", "top_hidden", "receivers", "givers"] {
+        let (src, dst) = (format!("{}:{}", from, suffix), format!("{}:{}", to, suffix));
+        if !dry_run { conn.sunionstore::<_, _, ()>(&dst, &[&dst, &src])?; }
+    }
+
+    // complaints and deals are numbered per group, so they get new ids and warnings follow them
+    let (complaints, new_complaints) = copy_records(&mut conn, from, to, "complaint", dry_run)?;
+    report.complaints = complaints.len();
+    report.new_complaints = new_complaints;
+    for (src, dst) in matching_keys(&mut conn, from, to, "*:warnings")? {
+        report.warned_users += 1;
+        let ids: Vec<u64> = conn.smembers(&src)?;
+        let ids: Vec<_> = ids.iter().filter_map(|id| complaints.get(id)).collect();
+        if !dry_run && !ids.is_empty() { conn.sadd::<_, _, ()>(&dst, ids)?; }
+    }
+
+    let (deals, new_deals) = copy_records(&mut conn, from, to, "deal", dry_run)?;
+    report.deals = deals.len();
+    report.new_deals = new_deals;
+    // confirmed deals are kept as sets of counterparties, which don't depend on the group
+    if !dry_run {
+        for (src, dst) in matching_keys(&mut conn, from, to, "*:deals")? {
+            conn.sunionstore::<_, _, ()>(&dst, &[&dst, &src])?;
+        }
+    }
+
+    // settings the new group has chosen already stay, and so do joins it has seen
+    if !dry_run {
+        for suffix in ["settings", "joined"] {
+            merge_hash(&mut conn, &format!("{}:{}", from, suffix), &format!("{}:{}", to, suffix))?;
+        }
+    }
+
+    if !with_ads { return Ok(report) }
+
+    for (src, dst) in matching_keys(&mut conn, from, to, "ad:*")? {
+        report.ads += 1;
+        let exists: bool = conn.exists(&dst)?;
+        if exists { continue }
+
+        report.new_ads += 1;
+        if !dry_run {
+            let json: String = conn.get(&src)?;
+            conn.set::<_, _, ()>(&dst, json)?;
+        }
+    }
+
+    if !dry_run {
+        for (src, dst) in matching_keys(&mut conn, from, to, "*:ads")? {
+            conn.sunionstore::<_, _, ()>(&dst, &[&dst, &src])?;
+        }
+
+        let (src, dst) = (format!("{}:published", from), format!("{}:published", to));
+        conn.zunionstore_min::<_, _, ()>(&dst, &[&dst, &src])?;
+        merge_hash(&mut conn, &format!("{}:legacy_authors", from), &format!("{}:legacy_authors", to))?;
+    }
+
+    Ok(report)
+}
+
+/// Keys of the old group matching the pattern, paired with their names in the new group.
+fn matching_keys(
+    conn: &mut Connection,
+    from: ChatId,
+    to: ChatId,
+    pattern: &str,
+) -> RedisResult<Vec<(String, String)>> {
+    let prefix = format!("{}:", from);
+    let keys: Vec<String> = conn.scan_match(format!("{}{}", prefix, pattern))?.collect();
+
+    Ok(keys.into_iter()
+        .map(|key| {
+            let renamed = format!("{}:{}", to, &key[prefix.len()..]);
+            (key, renamed)
+        })
+        .collect())
+}
+
+/// Copies records named `{group}:{kind}:{id}` to the new group under ids it hands out,
+/// remembering the copies, so running the migration again doesn't copy them twice.
+///
+/// Returns new ids of all the records by their old ids, and the number of records copied this time.
+/// On a dry run records aren't copied and only those copied earlier have new ids.
+fn copy_records(
+    conn: &mut Connection,
+    from: ChatId,
+    to: ChatId,
+    kind: &str,
+    dry_run: bool,
+) -> RedisResult<(HashMap<u64, u64>, usize)> {
+    let copies_key = format!("{}:migrated_{}s", to, kind);
+    let next_id_key = format!("{}:{}:next_id", to, kind);
+    let prefix = format!("{}:{}:", from, kind);
+    let keys: Vec<String> = conn.scan_match(format!("{}*", prefix))?.collect();
+
+    let mut ids = HashMap::new();
+    let mut copied = 0;
+    for key in keys {
+        // the counter of ids shares the prefix
+        let Ok(old_id) = key[prefix.len()..].parse::<u64>() else { continue };
+
+        let field = format!("{}:{}", from, old_id);
+        let copy: Option<u64> = conn.hget(&copies_key, &field)?;
+        if let Some(new_id) = copy {
+            ids.insert(old_id, new_id);
+            continue;
+        }
+
+        copied += 1;
+        if dry_run { continue }
+
+        let json: String = conn.get(&key)?;
+        let Ok(mut record) = serde_json::from_str::<serde_json::Value>(&json) else {
+            log::error!("broken {} {}", kind, old_id);
+            continue
+        };
+        let new_id: u64 = conn.incr(&next_id_key, 1)?;
+        record["id"] = new_id.into();
+        redis::pipe()
+            .atomic()
+            .set(format!("{}:{}:{}", to, kind, new_id), record.to_string())
+            .hset(&copies_key, &field, new_id)
+            .query::<()>(conn)?;
+        ids.insert(old_id, new_id);
+    }
+
+    Ok((ids, copied))
+}
+
+fn merge_hash(conn: &mut Connection, src: &str, dst: &str) -> RedisResult<()> {
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = conn.hgetall(src)?;
+    for (field, value) in entries {
+        conn.hset_nx::<_, _, _, ()>(dst, field, value)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! Roles granted with `/grant`. The owner comes from the config and is not stored.
+
+use std::collections::HashMap;
+use redis::{Commands, RedisResult};
+use teloxide::prelude::*;
+use crate::types::Role;
+
+/// Hash of user id to the name of the role.
+const ROLES_KEY: &str = "roles";
+
+/// Granted roles. Roles this version doesn't know are skipped.
+pub fn load(client: &redis::Client) -> RedisResult<HashMap<UserId, Role>> {
+    let mut conn = client.get_connection()?;
+    let roles: HashMap<u64, String> = conn.hgetall(ROLES_KEY)?;
+
+    Ok(roles.into_iter()
+        .filter_map(|(user_id, role)| match role.parse() {
+            Ok(role) => Some((UserId(user_id), role)),
+            Err(_) => {
+                log::warn!("unknown role {} of {}", role, user_id);
+                None
+            }
+        })
+        .collect())
+}
+
+pub fn grant(user_id: UserId, role: Role, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.hset(ROLES_KEY, user_id.0, role.to_string())
+}
+
+pub fn revoke(user_id: UserId, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.hdel(ROLES_KEY, user_id.0)
+}
@@ -0,0 +1,105 @@
+use redis::{Commands, RedisResult};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, User};
+use crate::site::form::Form;
+
+/// Ad waiting in the queue to be published at a given time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledAd {
+    pub id: u64,
+    /// Kept whole, since the ad header is rendered from it at publication time
+    pub author: User,
+    /// Unix time to publish the ad at
+    pub publish_at: u64,
+    /// Private message with the scheduled ad and its controls
+    pub report_msg_id: Option<MessageId>,
+    /// Failed attempts to publish the ad
+    #[serde(default)]
+    pub attempts: u32,
+    pub form: Form,
+}
+
+fn queue_key(group_id: ChatId) -> String {
+    format!("{}:scheduled", group_id)
+}
+
+fn scheduled_key(group_id: ChatId, id: u64) -> String {
+    format!("{}:scheduled:{}", group_id, id)
+}
+
+fn next_id_key(group_id: ChatId) -> String {
+    format!("{}:scheduled:next_id", group_id)
+}
+
+/// Set of ids of user's ads waiting in the queue.
+pub fn authored_key(group_id: ChatId, user_id: UserId) -> String {
+    format!("{}:{}:scheduled", group_id, user_id.0)
+}
+
+pub fn next_id(group_id: ChatId, client: &redis::Client) -> RedisResult<u64> {
+    let mut conn = client.get_connection()?;
+    conn.incr(next_id_key(group_id), 1)
+}
+
+/// Puts the ad into the queue, or updates it if it's already there.
+pub fn save(
+    group_id: ChatId,
+    ad: &ScheduledAd,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let json = serde_json::to_string(ad).expect("scheduled ad should be serializable");
+
+    redis::pipe()
+        .atomic()
+        .set(scheduled_key(group_id, ad.id), json)
+        .zadd(queue_key(group_id), ad.id, ad.publish_at)
+        .sadd(authored_key(group_id, ad.author.id), ad.id)
+        .query(&mut conn)
+}
+
+pub fn load(
+    group_id: ChatId,
+    id: u64,
+    client: &redis::Client,
+) -> RedisResult<Option<ScheduledAd>> {
+    let mut conn = client.get_connection()?;
+    let json: Option<String> = conn.get(scheduled_key(group_id, id))?;
+
+    Ok(json.and_then(|json| {
+        serde_json::from_str(&json)
+            .inspect_err(|e| log::error!("broken scheduled ad {}: {}", id, e))
+            .ok()
+    }))
+}
+
+/// Takes the ad out of the queue.
+///
+/// Returns false if it was not there anymore, so concurrent callers can tell
+/// which one of them is in charge of publishing it.
+pub fn remove(
+    group_id: ChatId,
+    ad: &ScheduledAd,
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let (removed, _, _): (usize, usize, usize) = redis::pipe()
+        .atomic()
+        .zrem(queue_key(group_id), ad.id)
+        .del(scheduled_key(group_id, ad.id))
+        .srem(authored_key(group_id, ad.author.id), ad.id)
+        .query(&mut conn)?;
+
+    Ok(removed > 0)
+}
+
+/// Ids of ads which should be published at or before the given unix time.
+pub fn due(
+    group_id: ChatId,
+    now: u64,
+    client: &redis::Client,
+) -> RedisResult<Vec<u64>> {
+    let mut conn = client.get_connection()?;
+    conn.zrangebyscore(queue_key(group_id), "-inf", now)
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use redis::{Commands, RedisResult};
+use teloxide::prelude::*;
+
+/// Hash of setting name to the value the group overrides it with.
+fn settings_key(group_id: ChatId) -> String {
+    format!("{}:settings", group_id)
+}
+
+/// Settings the group overrides, as they were given to `/set`.
+pub fn load(group_id: ChatId, client: &redis::Client) -> RedisResult<HashMap<String, String>> {
+    let mut conn = client.get_connection()?;
+    conn.hgetall(settings_key(group_id))
+}
+
+pub fn save(group_id: ChatId, key: &str, value: &str, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.hset(settings_key(group_id), key, value)
+}
+
+/// Returns the setting to the default. Returns false if it wasn't overridden.
+pub fn reset(group_id: ChatId, key: &str, client: &redis::Client) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let removed: usize = conn.hdel(settings_key(group_id), key)?;
+    Ok(removed > 0)
+}
@@ -0,0 +1,174 @@
+//! Giver-side index of stars, so users can see whom they starred.
+//!
+//! Both the key of the index and its entries are derived from the giver id and a server
+//! secret, and receivers are stored encrypted, so a Redis dump alone doesn't tell who
+//! starred whom.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use redis::{Commands, RedisResult};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use teloxide::prelude::*;
+
+const NONCE_LEN: usize = 12;
+
+/// User starred by the giver, as they were named at the time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StarredUser {
+    pub id: UserId,
+    pub name: String,
+}
+
+/// Secrets of a single giver: one names the index and its entries, the other encrypts them.
+struct GiverKeys {
+    tag_key: [u8; 32],
+    cipher: ChaCha20Poly1305,
+}
+
+impl GiverKeys {
+    fn new(giver: UserId, secret: &[u8]) -> Self {
+        let tag_key = derive(secret, b"starred:tag", giver);
+        let cipher_key = derive(secret, b"starred:cipher", giver);
+        GiverKeys {
+            tag_key,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&cipher_key)),
+        }
+    }
+
+    fn tag(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.tag_key).expect("hmac takes any key");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn index_key(&self, group_id: ChatId) -> String {
+        format!("{}:starred:{}", group_id, hex::encode(self.tag(b"index")))
+    }
+
+    fn field(&self, receiver: UserId) -> Vec<u8> {
+        self.tag(&receiver.0.to_be_bytes())
+    }
+
+    fn encrypt(&self, user: &StarredUser) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let json = serde_json::to_vec(user).expect("starred user should be serializable");
+        let ciphertext = self.cipher.encrypt(&nonce, &json[..]).expect("encryption can't fail");
+        [&nonce[..], &ciphertext[..]].concat()
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Option<StarredUser> {
+        if data.len() < NONCE_LEN { return None }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let json = self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+fn derive(secret: &[u8], purpose: &[u8], giver: UserId) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("hmac takes any key");
+    mac.update(purpose);
+    mac.update(&giver.0.to_be_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+pub fn add(
+    group_id: ChatId,
+    giver: UserId,
+    receiver: &StarredUser,
+    secret: &[u8],
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let keys = GiverKeys::new(giver, secret);
+    conn.hset(keys.index_key(group_id), keys.field(receiver.id), keys.encrypt(receiver))
+}
+
+pub fn remove(
+    group_id: ChatId,
+    giver: UserId,
+    receiver: UserId,
+    secret: &[u8],
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let keys = GiverKeys::new(giver, secret);
+    conn.hdel(keys.index_key(group_id), keys.field(receiver))
+}
+
+pub fn contains(
+    group_id: ChatId,
+    giver: UserId,
+    receiver: UserId,
+    secret: &[u8],
+    client: &redis::Client,
+) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let keys = GiverKeys::new(giver, secret);
+    conn.hexists(keys.index_key(group_id), keys.field(receiver))
+}
+
+/// Everyone the giver starred since the index exists.
+pub fn list(
+    group_id: ChatId,
+    giver: UserId,
+    secret: &[u8],
+    client: &redis::Client,
+) -> RedisResult<Vec<StarredUser>> {
+    let mut conn = client.get_connection()?;
+    let keys = GiverKeys::new(giver, secret);
+    let entries: Vec<Vec<u8>> = conn.hvals(keys.index_key(group_id))?;
+
+    Ok(entries.iter().filter_map(|data| {
+        keys.decrypt(data).or_else(|| {
+            log::error!("undecryptable starred entry of {}", giver);
+            None
+        })
+    }).collect())
+}
+
+/// Moves the index of the giver made with a legacy secret under the current one.
+/// `secrets` go the same way as star salts do, the current one first.
+/// Returns the number of moved entries.
+pub fn rekey(
+    group_id: ChatId,
+    giver: UserId,
+    secrets: &[Vec<u8>],
+    client: &redis::Client,
+) -> RedisResult<usize> {
+    let mut conn = client.get_connection()?;
+    let current = GiverKeys::new(giver, &secrets[0]);
+
+    let mut moved = 0;
+    for secret in &secrets[1..] {
+        let legacy = GiverKeys::new(giver, secret);
+        let entries: Vec<Vec<u8>> = conn.hvals(legacy.index_key(group_id))?;
+        for user in entries.iter().filter_map(|data| legacy.decrypt(data)) {
+            conn.hset::<_, _, _, ()>(current.index_key(group_id), current.field(user.id), current.encrypt(&user))?;
+            moved += 1;
+        }
+        conn.del::<_, ()>(legacy.index_key(group_id))?;
+    }
+
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::prelude::*;
+    use super::{GiverKeys, StarredUser};
+
+    #[test]
+    fn only_giver_can_read_entries() {
+        let user = StarredUser { id: UserId(195125422), name: "Вася".to_string() };
+
+        let keys = GiverKeys::new(UserId(279838373), b"makaroshki");
+        let data = keys.encrypt(&user);
+        assert_eq!(keys.decrypt(&data), Some(user));
+
+        let other = GiverKeys::new(UserId(279838374), b"makaroshki");
+        assert_eq!(other.decrypt(&data), None);
+        assert_ne!(keys.index_key(ChatId(-1)), other.index_key(ChatId(-1)));
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use redis::{Commands, RedisResult};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use crate::store::{stars_key, window_start};
+
+/// Place of a user in the group leaderboard.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopEntry {
+    pub id: UserId,
+    /// Name rendered as a link to the user
+    pub link: String,
+    pub stars: usize,
+}
+
+fn hidden_key(group_id: ChatId) -> String {
+    format!("{}:top_hidden", group_id)
+}
+
+fn cache_key(group_id: ChatId) -> String {
+    format!("{}:top", group_id)
+}
+
+/// Exists while the cached leaderboard is recent enough.
+fn fresh_key(group_id: ChatId) -> String {
+    format!("{}:top:fresh", group_id)
+}
+
+/// Set of users who have ever been given a star in the group.
+pub fn receivers_key(group_id: ChatId) -> String {
+    format!("{}:receivers", group_id)
+}
+
+/// Star counts of everyone who has stars within the window and didn't hide from the leaderboard,
+/// most starred first.
+pub fn star_counts(
+    group_id: ChatId,
+    window: Option<u64>,
+    client: &redis::Client,
+) -> RedisResult<Vec<(UserId, usize)>> {
+    let mut conn = client.get_connection()?;
+    let receivers: Vec<u64> = conn.smembers(receivers_key(group_id))?;
+    let hidden: Vec<u64> = conn.smembers(hidden_key(group_id))?;
+    let receivers: Vec<_> = receivers.into_iter().filter(|id| !hidden.contains(id)).collect();
+
+    let mut pipe = redis::pipe();
+    for user_id in &receivers {
+        pipe.zcount(stars_key(group_id, UserId(*user_id)), window_start(window), "+inf");
+    }
+    let stars: Vec<usize> = pipe.query(&mut conn)?;
+
+    let mut counts: Vec<_> = receivers.into_iter().map(UserId)
+        .zip(stars)
+        .filter(|(_, stars)| *stars > 0)
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    Ok(counts)
+}
+
+/// Fills the set of star receivers for stars given before it existed.
+/// Returns the number of groups it was filled for.
+pub fn backfill_receivers(client: &redis::Client) -> RedisResult<usize> {
+    let mut conn = client.get_connection()?;
+    let keys: Vec<String> = conn.scan_match("*:*:stars")?.collect();
+
+    let mut receivers: HashMap<ChatId, Vec<u64>> = HashMap::new();
+    for key in keys {
+        let mut parts = key.split(':');
+        let (Some(group_id), Some(user_id)) = (parts.next(), parts.next()) else { continue };
+        let (Ok(group_id), Ok(user_id)) = (group_id.parse(), user_id.parse()) else { continue };
+        receivers.entry(ChatId(group_id)).or_default().push(user_id);
+    }
+
+    for (group_id, user_ids) in &receivers {
+        conn.sadd::<_, _, ()>(receivers_key(*group_id), user_ids)?;
+    }
+    Ok(receivers.len())
+}
+
+/// Hides the user from the leaderboard or shows them again. Returns whether they are hidden now.
+pub fn toggle_hidden(group_id: ChatId, user_id: UserId, client: &redis::Client) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    let key = hidden_key(group_id);
+    let removed: usize = conn.srem(&key, user_id.0)?;
+    if removed == 0 {
+        conn.sadd::<_, _, ()>(&key, user_id.0)?;
+
+        // the user should disappear right away, while showing up again can wait for the rebuild
+        if let Some(mut top) = load_cached(group_id, client)? {
+            top.retain(|entry| entry.id != user_id);
+            let json = serde_json::to_string(&top).expect("leaderboard should be serializable");
+            conn.set::<_, _, ()>(cache_key(group_id), json)?;
+        }
+    }
+
+    invalidate(group_id, client)?;
+    Ok(removed == 0)
+}
+
+/// Leaderboard as of the last rebuild, however old it is.
+pub fn load_cached(group_id: ChatId, client: &redis::Client) -> RedisResult<Option<Vec<TopEntry>>> {
+    let mut conn = client.get_connection()?;
+    let json: Option<String> = conn.get(cache_key(group_id))?;
+
+    Ok(json.and_then(|json| {
+        serde_json::from_str(&json)
+            .inspect_err(|e| log::error!("broken leaderboard cache: {}", e))
+            .ok()
+    }))
+}
+
+/// Caches the leaderboard, so it is served for `ttl` seconds before getting rebuilt.
+/// It is still served after that until the rebuild is done.
+pub fn save_cached(
+    group_id: ChatId,
+    top: &[TopEntry],
+    ttl: u64,
+    client: &redis::Client,
+) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    let json = serde_json::to_string(top).expect("leaderboard should be serializable");
+    redis::pipe()
+        .atomic()
+        .set(cache_key(group_id), json)
+        .set_ex(fresh_key(group_id), 1, ttl)
+        .query(&mut conn)
+}
+
+pub fn is_fresh(group_id: ChatId, client: &redis::Client) -> RedisResult<bool> {
+    let mut conn = client.get_connection()?;
+    conn.exists(fresh_key(group_id))
+}
+
+/// Makes the leaderboard get rebuilt soon, serving the cached one meanwhile.
+pub fn invalidate(group_id: ChatId, client: &redis::Client) -> RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    conn.del(fresh_key(group_id))
+}
@@ -1,32 +1,156 @@
 pub mod swappy_user;
 pub mod swappy_bot;
+pub mod posting_limits;
+pub mod star_rules;
+pub mod group_settings;
+pub mod role;
 
 pub use swappy_user::{SwappyUser, ToSwappyUser};
+pub use posting_limits::{PostingLimits, Rejection};
+pub use star_rules::{StarRefusal, StarRules};
+pub use group_settings::{GroupSettings, SettingError};
+pub use role::Role;
 
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicI64, Ordering};
+use std::collections::HashMap;
+use std::sync::RwLock;
 use teloxide::Bot;
 use teloxide::prelude::UserId;
 use teloxide::types::ChatId;
 use url::Url;
+use crate::store::{groups, settings};
 
 // #[derive(Clone)]
 pub struct AppConfig {
     pub app_url: Url,
+    /// Origins allowed to call the site api, the mini app origin first
+    pub cors_origins: Vec<String>,
     pub bot: Bot,
     pub redis_client: redis::Client,
+    /// Owner of the bot
     pub bot_maintainer: UserId,
-    pub group_id: Arc<AtomicI64>,
+    /// Roles granted to other users
+    pub roles: RwLock<HashMap<UserId, Role>>,
+    /// Registered groups, the default one first
+    pub groups: RwLock<Vec<ChatId>>,
     pub bot_token: String,
+    /// Key inline buttons are signed with
+    pub callback_secret: String,
+    /// Salts of star hashes, the current one first, then legacy ones from newest to oldest
+    pub star_salts: Vec<Vec<u8>>,
+    /// Settings of groups that didn't override them
+    pub default_settings: GroupSettings,
 }
 
 impl AppConfig {
+    /// The default group, for those who didn't choose one.
     pub fn group_id(&self) -> ChatId {
-        ChatId(self.group_id.load(Ordering::Relaxed))
+        self.groups.read().unwrap().first().copied().unwrap_or(ChatId(0))
     }
 
+    /// Makes the group the default one, registering it if needed.
     pub fn set_group_id(&self, gid: i64) {
-        self.group_id.store(gid, Ordering::Relaxed)
+        let mut groups = self.groups.write().unwrap();
+        groups.retain(|group| group.0 != gid);
+        groups.insert(0, ChatId(gid));
+    }
+
+    pub fn groups(&self) -> Vec<ChatId> {
+        self.groups.read().unwrap().clone()
+    }
+
+    pub fn is_registered(&self, group_id: ChatId) -> bool {
+        self.groups.read().unwrap().contains(&group_id)
+    }
+
+    /// Returns false if the group was registered already.
+    pub fn add_group(&self, group_id: ChatId) -> bool {
+        let mut groups = self.groups.write().unwrap();
+        if groups.contains(&group_id) { return false }
+        groups.push(group_id);
+        true
+    }
+
+    /// Returns false if there was no such group.
+    pub fn remove_group(&self, group_id: ChatId) -> bool {
+        let mut groups = self.groups.write().unwrap();
+        let len = groups.len();
+        groups.retain(|group| *group != group_id);
+        groups.len() != len
+    }
+
+    /// Group the user works with in private chat: the chosen one if it's still registered,
+    /// the default one otherwise.
+    pub fn group_of(&self, user_id: UserId) -> ChatId {
+        match groups::selected(user_id, &self.redis_client) {
+            Ok(Some(group_id)) if self.is_registered(group_id) => group_id,
+            Ok(_) => self.group_id(),
+            Err(e) => {
+                log::error!("failed to get selected group: {}", e.to_string());
+                self.group_id()
+            }
+        }
+    }
+
+    /// Defaults with the overrides of the group applied. Overrides that don't parse
+    /// anymore are skipped.
+    pub fn settings_of(&self, group_id: ChatId) -> GroupSettings {
+        let mut group_settings = self.default_settings.clone();
+        let overrides = settings::load(group_id, &self.redis_client).unwrap_or_else(|e| {
+            log::error!("failed to load settings of {}: {}", group_id, e.to_string());
+            Default::default()
+        });
+
+        for (key, value) in overrides {
+            if let Err(e) = group_settings.set(&key, &value) {
+                log::warn!("bad setting {} of {}: {}", key, group_id, e);
+            }
+        }
+        group_settings
+    }
+
+    /// Role of the user, `None` for ordinary members.
+    pub fn role_of(&self, user_id: UserId) -> Option<Role> {
+        if user_id == self.bot_maintainer { return Some(Role::Owner) }
+        self.roles.read().unwrap().get(&user_id).copied()
+    }
+
+    /// Whether the user has the role or one above it.
+    pub fn has_role(&self, user_id: UserId, role: Role) -> bool {
+        self.role_of(user_id).is_some_and(|own| own >= role)
+    }
+
+    /// Gives the user the role, or takes their role away if `None`.
+    pub fn set_role(&self, user_id: UserId, role: Option<Role>) {
+        let mut roles = self.roles.write().unwrap();
+        match role {
+            Some(role) => roles.insert(user_id, role),
+            None => roles.remove(&user_id),
+        };
+    }
+
+    /// Users with the role or one above it, the owner first.
+    pub fn staff(&self, role: Role) -> Vec<(UserId, Role)> {
+        let mut staff: Vec<_> = self.roles.read().unwrap().iter()
+            .filter(|(user_id, own)| **own >= role && **user_id != self.bot_maintainer)
+            .map(|(user_id, own)| (*user_id, *own))
+            .collect();
+        staff.sort_by_key(|(user_id, own)| (std::cmp::Reverse(*own), user_id.0));
+        staff.insert(0, (self.bot_maintainer, Role::Owner));
+        staff
+    }
+
+    pub fn star_salt(&self) -> &[u8] {
+        &self.star_salts[0]
+    }
+
+    /// Salts stars were hashed with before, still recognized until the stars get re-keyed.
+    pub fn legacy_star_salts(&self) -> &[Vec<u8>] {
+        &self.star_salts[1..]
+    }
+
+    /// Key for signing callback data of inline buttons.
+    pub fn callback_key(&self) -> &[u8] {
+        self.callback_secret.as_bytes()
     }
 }
\ No newline at end of file
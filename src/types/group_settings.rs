@@ -0,0 +1,238 @@
+use std::fmt::{Display, Formatter};
+use serde::Serialize;
+use crate::store::MONTH_SECS;
+use super::{PostingLimits, StarRules};
+
+/// Languages the mini app can be shown in.
+pub const LANGUAGES: &[&str] = &["ru", "en"];
+
+/// Everything a group can tune for itself. Groups start with the defaults of the deployment
+/// and override single settings with `/set`.
+#[derive(Debug, Clone)]
+pub struct GroupSettings {
+    /// Ads are taken down after this many seconds, kept forever if `None`
+    pub ad_ttl: Option<u64>,
+    pub posting_limits: PostingLimits,
+    /// Only stars given within this many seconds count, all of them if `None`
+    pub star_window: Option<u64>,
+    pub star_rules: StarRules,
+    /// Currencies ads can deal in, any if empty
+    pub currencies: Vec<String>,
+    /// Payment methods the mini app offers to pick from
+    pub payment_methods: Vec<String>,
+    /// Rules of the group shown by /rules
+    pub rules: String,
+    /// Language of the mini app, one of [`LANGUAGES`]
+    pub language: String,
+}
+
+impl Default for GroupSettings {
+    fn default() -> Self {
+        GroupSettings {
+            ad_ttl: None,
+            posting_limits: PostingLimits::default(),
+            star_window: None,
+            star_rules: StarRules::default(),
+            currencies: vec![],
+            payment_methods: vec![],
+            rules: String::default(),
+            language: LANGUAGES[0].to_string(),
+        }
+    }
+}
+
+/// Part of the settings the mini app needs to build the form.
+#[derive(Serialize, Debug)]
+pub struct PublicSettings<'a> {
+    pub currencies: &'a [String],
+    pub payment_methods: &'a [String],
+    pub rules: &'a str,
+    pub language: &'a str,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SettingError {
+    UnknownKey,
+    BadValue(&'static str),
+}
+
+impl GroupSettings {
+    /// Names of the settings as `/set` takes them.
+    pub const KEYS: &'static [&'static str] = &[
+        "ad_ttl",
+        "max_active_ads",
+        "post_interval",
+        "stars_per_extra_ad",
+        "star_window",
+        "star_min_member_age",
+        "star_min_giver_stars",
+        "star_daily_budget",
+        "currencies",
+        "payment_methods",
+        "rules",
+        "language",
+    ];
+
+    /// Parses the value and assigns it to the setting.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingError> {
+        let value = value.trim();
+        match key {
+            "ad_ttl" => self.ad_ttl = parse_optional(value, parse_duration)?,
+            "max_active_ads" => self.posting_limits.max_active_ads = parse_count(value)?,
+            "post_interval" => self.posting_limits.min_interval = parse_duration(value)?,
+            "stars_per_extra_ad" => self.posting_limits.stars_per_extra_ad = parse_optional(value, parse_count)?,
+            "star_window" => self.star_window = parse_optional(value, parse_duration)?,
+            "star_min_member_age" => self.star_rules.min_membership = parse_duration(value)?,
+            "star_min_giver_stars" => self.star_rules.min_giver_stars = parse_count(value)?,
+            "star_daily_budget" => self.star_rules.daily_budget = parse_optional(value, parse_count)?,
+            "currencies" => self.currencies = parse_list(value).into_iter().map(|c| c.to_uppercase()).collect(),
+            "payment_methods" => self.payment_methods = parse_list(value),
+            "rules" => self.rules = value.to_string(),
+            "language" => {
+                if !LANGUAGES.contains(&value) {
+                    return Err(SettingError::BadValue("one of supported languages, e.g. ru"));
+                }
+                self.language = value.to_string();
+            }
+            _ => return Err(SettingError::UnknownKey),
+        }
+        Ok(())
+    }
+
+    /// Value of the setting in the form [`GroupSettings::set`] takes it.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "ad_ttl" => format_optional(self.ad_ttl, format_duration),
+            "max_active_ads" => self.posting_limits.max_active_ads.to_string(),
+            "post_interval" => format_duration(self.posting_limits.min_interval),
+            "stars_per_extra_ad" => format_optional(self.posting_limits.stars_per_extra_ad, |n| n.to_string()),
+            "star_window" => format_optional(self.star_window, format_duration),
+            "star_min_member_age" => format_duration(self.star_rules.min_membership),
+            "star_min_giver_stars" => self.star_rules.min_giver_stars.to_string(),
+            "star_daily_budget" => format_optional(self.star_rules.daily_budget, |n| n.to_string()),
+            "currencies" => self.currencies.join(", "),
+            "payment_methods" => self.payment_methods.join(", "),
+            "rules" => self.rules.clone(),
+            "language" => self.language.clone(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn public(&self) -> PublicSettings<'_> {
+        PublicSettings {
+            currencies: &self.currencies,
+            payment_methods: &self.payment_methods,
+            rules: &self.rules,
+            language: &self.language,
+        }
+    }
+
+    /// Whether ads can deal in the currency.
+    pub fn allows_currency(&self, currency: &str) -> bool {
+        self.currencies.is_empty() || self.currencies.iter().any(|c| c.eq_ignore_ascii_case(currency))
+    }
+
+    /// Whether ads can offer the payment method among the picked ones.
+    pub fn allows_payment_method(&self, method: &str) -> bool {
+        self.payment_methods.is_empty() || self.payment_methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+    }
+}
+
+impl Display for SettingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingError::UnknownKey => write!(f, "unknown setting, known ones: {}", GroupSettings::KEYS.join(", ")),
+            SettingError::BadValue(expected) => write!(f, "expected {}", expected),
+        }
+    }
+}
+
+const UNITS: &[(&str, u64)] = &[
+    ("mo", MONTH_SECS),
+    ("w", 7 * 24 * 60 * 60),
+    ("d", 24 * 60 * 60),
+    ("h", 60 * 60),
+    ("m", 60),
+    ("s", 1),
+];
+
+/// Parses durations like `7d`, `12h` or `3mo` into seconds.
+fn parse_duration(value: &str) -> Result<u64, SettingError> {
+    const EXPECTED: &str = "duration like 30m, 12h, 7d, 2w or 3mo";
+
+    if value == "0" { return Ok(0) }
+    let split = value.find(|c: char| !c.is_ascii_digit()).ok_or(SettingError::BadValue(EXPECTED))?;
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| SettingError::BadValue(EXPECTED))?;
+    let (_, secs) = UNITS.iter().find(|(name, _)| *name == unit).ok_or(SettingError::BadValue(EXPECTED))?;
+    number.checked_mul(*secs).ok_or(SettingError::BadValue(EXPECTED))
+}
+
+/// Formats seconds with the largest unit that fits them exactly.
+fn format_duration(secs: u64) -> String {
+    if secs == 0 { return "0".to_string() }
+    let (name, unit) = UNITS.iter().find(|(_, unit)| secs.is_multiple_of(*unit)).expect("seconds divide anything");
+    format!("{}{}", secs / unit, name)
+}
+
+fn parse_count(value: &str) -> Result<usize, SettingError> {
+    value.parse().map_err(|_| SettingError::BadValue("non-negative number"))
+}
+
+/// `off` turns optional settings off.
+fn parse_optional<T>(value: &str, parse: fn(&str) -> Result<T, SettingError>) -> Result<Option<T>, SettingError> {
+    if value == "off" { Ok(None) } else { parse(value).map(Some) }
+}
+
+fn format_optional<T>(value: Option<T>, format: fn(T) -> String) -> String {
+    value.map(format).unwrap_or_else(|| "off".to_string())
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupSettings, SettingError};
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn settings_are_parsed() {
+        let mut settings = GroupSettings::default();
+
+        settings.set("ad_ttl", "7d").unwrap();
+        settings.set("stars_per_extra_ad", "off").unwrap();
+        settings.set("currencies", "eur, usd,rub").unwrap();
+        settings.set("payment_methods", "bizum, sber").unwrap();
+
+        assert_eq!(settings.ad_ttl, Some(7 * DAY));
+        assert_eq!(settings.posting_limits.stars_per_extra_ad, None);
+        assert_eq!(settings.currencies, ["EUR", "USD", "RUB"]);
+        assert!(settings.allows_currency("rub"));
+        assert!(!settings.allows_currency("GBP"));
+        assert!(settings.allows_payment_method("Bizum"));
+        assert!(!settings.allows_payment_method("paypal"));
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let mut settings = GroupSettings::default();
+        for (key, value) in [("ad_ttl", "2w"), ("post_interval", "90m"), ("star_window", "3mo"), ("star_daily_budget", "off")] {
+            settings.set(key, value).unwrap();
+            assert_eq!(settings.get(key).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn bad_settings_are_rejected() {
+        let mut settings = GroupSettings::default();
+
+        assert_eq!(settings.set("nope", "1"), Err(SettingError::UnknownKey));
+        assert!(matches!(settings.set("ad_ttl", "7 days"), Err(SettingError::BadValue(_))));
+        assert!(matches!(settings.set("max_active_ads", "-1"), Err(SettingError::BadValue(_))));
+        assert!(matches!(settings.set("language", "xx"), Err(SettingError::BadValue(_))));
+    }
+}
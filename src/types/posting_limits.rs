@@ -0,0 +1,113 @@
+use std::fmt::{Display, Formatter};
+use serde::Serialize;
+
+/// Limits on publishing new ads. Edits are never limited.
+#[derive(Debug, Clone)]
+pub struct PostingLimits {
+    /// How many active ads a user can have at once
+    pub max_active_ads: usize,
+    /// Minimum time between two new ads of a user, in seconds
+    pub min_interval: u64,
+    /// If set, every this many stars allow one more active ad
+    pub stars_per_extra_ad: Option<usize>,
+}
+
+impl Default for PostingLimits {
+    fn default() -> Self {
+        PostingLimits {
+            max_active_ads: 3,
+            min_interval: 60 * 60,
+            stars_per_extra_ad: None,
+        }
+    }
+}
+
+/// Reason to refuse a new ad. Serialized as is for the mini app.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum Rejection {
+    TooManyAds { limit: usize },
+    TooSoon { retry_after: u64 },
+}
+
+impl PostingLimits {
+    pub fn quota(&self, star_count: usize) -> usize {
+        let extra = self.stars_per_extra_ad
+            .filter(|per| *per > 0)
+            .map(|per| star_count / per)
+            .unwrap_or_default();
+
+        self.max_active_ads + extra
+    }
+
+    /// `cooldown` is how many seconds are left until the user's last post stops blocking new ones.
+    pub fn check(&self, active_ads: usize, star_count: usize, cooldown: Option<u64>) -> Result<(), Rejection> {
+        let limit = self.quota(star_count);
+        if active_ads >= limit {
+            return Err(Rejection::TooManyAds { limit });
+        }
+
+        match cooldown {
+            Some(retry_after) if retry_after > 0 => Err(Rejection::TooSoon { retry_after }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Rejection {
+    /// Body of the error response for the mini app, with a message ready to be shown.
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::to_value(self).expect("rejection should be serializable");
+        value["message"] = self.to_string().into();
+        value.to_string()
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::TooManyAds { limit } => write!(
+                f, "Нельзя иметь больше {} активных объявлений. Снимите одно из них, чтобы опубликовать новое",
+                limit,
+            ),
+            Rejection::TooSoon { retry_after } => write!(
+                f, "Новое объявление можно будет опубликовать через {} мин.",
+                retry_after.div_ceil(60),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PostingLimits, Rejection};
+
+    #[test]
+    fn quota_scales_with_stars() {
+        let limits = PostingLimits { max_active_ads: 2, min_interval: 0, stars_per_extra_ad: Some(5) };
+
+        assert_eq!(limits.quota(0), 2);
+        assert_eq!(limits.quota(4), 2);
+        assert_eq!(limits.quota(11), 4);
+    }
+
+    #[test]
+    fn limits_are_checked() {
+        let limits = PostingLimits::default();
+
+        assert_eq!(limits.check(0, 0, None), Ok(()));
+        assert_eq!(limits.check(3, 100, None), Err(Rejection::TooManyAds { limit: 3 }));
+        assert_eq!(limits.check(1, 0, Some(60)), Err(Rejection::TooSoon { retry_after: 60 }));
+    }
+
+    #[test]
+    fn rejection_is_serialized_for_mini_app() {
+        let json: serde_json::Value = serde_json::from_str(
+            &Rejection::TooSoon { retry_after: 60 }.to_json()
+        ).unwrap();
+
+        assert_eq!(json["error"], "too_soon");
+        assert_eq!(json["retry_after"], 60);
+        assert_eq!(json["message"], "Новое объявление можно будет опубликовать через 1 мин.");
+    }
+}
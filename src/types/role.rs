@@ -0,0 +1,76 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// What a user may do with the bot, each role allowing everything the ones below it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Settles complaints and takes down any ad
+    Moderator,
+    /// Manages groups and their settings, appoints moderators
+    Maintainer,
+    /// The `bot_maintainer` of the config, appoints maintainers. Can't be granted.
+    Owner,
+}
+
+impl Role {
+    /// Roles in the order they are granted, lowest first.
+    pub const ALL: &'static [Role] = &[Role::Moderator, Role::Maintainer, Role::Owner];
+
+    /// Whether one with this role can change the role of a user from `current` to `new`.
+    /// Only roles below one's own can be granted or revoked.
+    pub fn can_assign(self, current: Option<Role>, new: Option<Role>) -> bool {
+        current.is_none_or(|current| current < self)
+            && new.is_none_or(|new| new < self && new != Role::Owner)
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Moderator => write!(f, "moderator"),
+            Role::Maintainer => write!(f, "maintainer"),
+            Role::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "moderator" => Ok(Role::Moderator),
+            "maintainer" => Ok(Role::Maintainer),
+            "owner" => Ok(Role::Owner),
+            _ => Err("expected moderator, maintainer or owner"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+
+    #[test]
+    fn roles_below_own_are_assigned() {
+        use Role::*;
+
+        assert!(Owner.can_assign(None, Some(Maintainer)));
+        assert!(Owner.can_assign(Some(Maintainer), None));
+        assert!(Maintainer.can_assign(None, Some(Moderator)));
+        assert!(Maintainer.can_assign(Some(Moderator), None));
+
+        assert!(!Owner.can_assign(None, Some(Owner)));
+        assert!(!Maintainer.can_assign(None, Some(Maintainer)));
+        assert!(!Maintainer.can_assign(Some(Maintainer), Some(Moderator)));
+        assert!(!Moderator.can_assign(None, Some(Moderator)));
+    }
+
+    #[test]
+    fn roles_round_trip() {
+        for role in Role::ALL {
+            assert_eq!(role.to_string().parse::<Role>(), Ok(*role));
+        }
+        assert!("admin".parse::<Role>().is_err());
+    }
+}
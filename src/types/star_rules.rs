@@ -0,0 +1,92 @@
+use std::fmt::{Display, Formatter};
+
+/// Rules against star rings made of fresh accounts. Defaults restrict nothing.
+#[derive(Debug, Clone, Default)]
+pub struct StarRules {
+    /// How long the giver has to be a member of the group, in seconds
+    pub min_membership: u64,
+    /// How many stars the giver has to have themselves
+    pub min_giver_stars: usize,
+    /// How many new stars a user can give a day
+    pub daily_budget: Option<usize>,
+}
+
+/// Reason a receiver didn't get a star.
+#[derive(Debug, PartialEq)]
+pub enum StarRefusal {
+    Yourself,
+    NotMember,
+    TooNew { wait: u64 },
+    TooFewStars { needed: usize },
+    BudgetSpent { budget: usize },
+    /// The star couldn't be stored
+    Failed,
+}
+
+impl StarRules {
+    /// Rules about the giver, the same for every receiver.
+    ///
+    /// `member_for` is the number of seconds since the giver joined the group.
+    pub fn check_giver(&self, member_for: u64, giver_stars: usize) -> Result<(), StarRefusal> {
+        if member_for < self.min_membership {
+            return Err(StarRefusal::TooNew { wait: self.min_membership - member_for });
+        }
+
+        if giver_stars < self.min_giver_stars {
+            return Err(StarRefusal::TooFewStars { needed: self.min_giver_stars });
+        }
+
+        Ok(())
+    }
+
+    /// Whether the giver can have given that many new stars today.
+    pub fn within_budget(&self, given_today: usize) -> bool {
+        self.daily_budget.is_none_or(|budget| given_today <= budget)
+    }
+}
+
+impl Display for StarRefusal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StarRefusal::Yourself => write!(f, "себе звезду вручить нельзя"),
+            StarRefusal::NotMember => write!(f, "не состоит в группе"),
+            StarRefusal::TooNew { wait } => write!(
+                f, "вручать звёзды можно будет через {} дн. после вступления в группу",
+                wait.div_ceil(24 * 60 * 60),
+            ),
+            StarRefusal::TooFewStars { needed } => write!(
+                f, "чтобы вручать звёзды, нужно самому иметь хотя бы {}⭐", needed,
+            ),
+            StarRefusal::BudgetSpent { budget } => write!(
+                f, "за сутки можно вручить не больше {} звёзд", budget,
+            ),
+            StarRefusal::Failed => write!(f, "что-то пошло не так, попробуйте позднее"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StarRefusal, StarRules};
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn giver_rules_are_checked() {
+        let rules = StarRules { min_membership: 7 * DAY, min_giver_stars: 1, daily_budget: None };
+
+        assert_eq!(rules.check_giver(8 * DAY, 1), Ok(()));
+        assert_eq!(rules.check_giver(5 * DAY, 1), Err(StarRefusal::TooNew { wait: 2 * DAY }));
+        assert_eq!(rules.check_giver(8 * DAY, 0), Err(StarRefusal::TooFewStars { needed: 1 }));
+        assert_eq!(StarRules::default().check_giver(0, 0), Ok(()));
+    }
+
+    #[test]
+    fn budget_is_counted() {
+        let rules = StarRules { daily_budget: Some(3), ..StarRules::default() };
+
+        assert!(rules.within_budget(3));
+        assert!(!rules.within_budget(4));
+        assert!(StarRules::default().within_budget(100));
+    }
+}
@@ -2,6 +2,7 @@ use teloxide::prelude::*;
 
 pub struct SwappyBot {
     pub bot: Bot,
+    #[allow(dead_code)]
     group_id: ChatId,
 }
 
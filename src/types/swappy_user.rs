@@ -1,25 +1,37 @@
 use std::fmt::{Display, Formatter};
-use std::sync::atomic::Ordering;
 use redis::{AsyncCommands, RedisResult};
 use redis::aio::MultiplexedConnection;
 use teloxide::{
     prelude::*,
     RequestError,
     types::{
-        MessageId
+        MessageId, User
     },
+    utils::html,
 };
-use crate::types::AppConfig;
+use crate::bot::REPOST_COOLDOWN_SECS;
+use crate::store::{self, ads, complaints, deals, scheduled};
+use crate::types::{AppConfig, GroupSettings, Rejection};
 
 pub trait ToSwappyUser<'a> {
-    fn with_config(self, app_config: &'a AppConfig) -> impl std::future::Future<Output = SwappyUser> + Send;
+    /// Binds the user to the group they work with, see [`AppConfig::group_of`].
+    fn with_config(self, app_config: &'a AppConfig) -> impl std::future::Future<Output = SwappyUser<'a>> + Send;
+
+    /// Binds the user to the given group.
+    fn in_group(self, app_config: &'a AppConfig, group_id: ChatId) -> impl std::future::Future<Output = SwappyUser<'a>> + Send;
 }
 
 impl<'a> ToSwappyUser<'a> for teloxide::types::User {
-    async fn with_config(self, app_config: &'a AppConfig) -> SwappyUser {
+    async fn with_config(self, app_config: &'a AppConfig) -> SwappyUser<'a> {
+        let group_id = app_config.group_of(self.id);
+        self.in_group(app_config, group_id).await
+    }
+
+    async fn in_group(self, app_config: &'a AppConfig, group_id: ChatId) -> SwappyUser<'a> {
         SwappyUser {
-            group_id: ChatId(app_config.group_id.load(Ordering::Relaxed)),
+            group_id,
             config: app_config,
+            settings: app_config.settings_of(group_id),
             tg_user: self,
             redis_conn: app_config.redis_client.get_multiplexed_async_connection().await.unwrap()
         }
@@ -29,17 +41,27 @@ impl<'a> ToSwappyUser<'a> for teloxide::types::User {
 pub struct SwappyUser<'a> {
     pub group_id: ChatId,
     pub config: &'a AppConfig,
+    /// Settings of the group as they were when the user was bound to it
+    pub settings: GroupSettings,
     pub tg_user: teloxide::types::User,
     redis_conn: MultiplexedConnection,
 }
 
 impl<'a> SwappyUser<'a> {
     fn ads_key(&self) -> String {
-        format!("{}:{}:ads", self.group_id.0, self.tg_user.id.0)
+        ads::authored_key(self.group_id, self.tg_user.id)
     }
 
     fn stars_key(&self) -> String {
-        format!("{}:{}:stars", self.group_id.0, self.tg_user.id.0)
+        store::stars_key(self.group_id, self.tg_user.id)
+    }
+
+    fn last_post_key(&self) -> String {
+        format!("{}:{}:last_post", self.group_id.0, self.tg_user.id.0)
+    }
+
+    fn repost_key(&self, message_id: MessageId) -> String {
+        format!("{}:{}:reposted", self.group_id.0, message_id.0)
     }
 
     pub async fn is_group_member(&self) -> Result<bool, RequestError> {
@@ -48,8 +70,20 @@ impl<'a> SwappyUser<'a> {
         Ok(o.kind.is_present())
     }
 
+    /// Stars counted in reputation, i.e. given within the configured window.
     pub async fn star_count(&mut self) -> RedisResult<usize> {
-        self.redis_conn.scard(self.stars_key()).await
+        let since = store::window_start(self.settings.star_window);
+        self.redis_conn.zcount(self.stars_key(), since, "+inf").await
+    }
+
+    /// Approved complaints about the user.
+    pub async fn warning_count(&mut self) -> RedisResult<usize> {
+        self.redis_conn.scard(complaints::warnings_key(self.group_id, self.tg_user.id)).await
+    }
+
+    /// Deals the user took part in, confirmed by both parties.
+    pub async fn deal_count(&mut self) -> RedisResult<usize> {
+        self.redis_conn.scard(deals::confirmed_key(self.group_id, self.tg_user.id)).await
     }
 
     pub async fn set_author(&mut self, message_id: MessageId) -> RedisResult<()> {
@@ -59,13 +93,101 @@ impl<'a> SwappyUser<'a> {
     pub async fn is_author(&mut self, message_id: MessageId) -> RedisResult<bool> {
         self.redis_conn.sismember(self.ads_key(), message_id.0).await
     }
+
+    /// Group message ids of user's active ads.
+    pub async fn ads(&mut self) -> RedisResult<Vec<MessageId>> {
+        let ids: Vec<i32> = self.redis_conn.smembers(self.ads_key()).await?;
+        Ok(ids.into_iter().map(MessageId).collect())
+    }
+
+    pub async fn unset_author(&mut self, message_id: MessageId) -> RedisResult<()> {
+        self.redis_conn.srem(self.ads_key(), message_id.0).await
+    }
+
+    /// Checks whether the user may publish one more ad right now.
+    pub async fn check_posting_limits(&mut self) -> RedisResult<Result<(), Rejection>> {
+        // queued ads will become active, so they count too
+        let active: usize = self.redis_conn.scard(self.ads_key()).await?;
+        let queued: usize = self.redis_conn.scard(scheduled::authored_key(self.group_id, self.tg_user.id)).await?;
+        let stars = self.star_count().await?;
+        let ttl: i64 = self.redis_conn.ttl(self.last_post_key()).await?;
+        let cooldown = if ttl > 0 { Some(ttl as u64) } else { None };
+
+        Ok(self.settings.posting_limits.check(active + queued, stars, cooldown))
+    }
+
+    /// Starts the minimum interval before the next new ad unless one is running already,
+    /// in which case returns the seconds left of it. Every new ad takes its turn this way
+    /// before it's published, so neither concurrent submissions nor scheduling several ads
+    /// get around the interval.
+    pub async fn reserve_posting_cooldown(&mut self) -> RedisResult<Option<u64>> {
+        let interval = self.settings.posting_limits.min_interval;
+        if interval == 0 { return Ok(None) }
+
+        let reserved: Option<String> = redis::cmd("SET")
+            .arg(self.last_post_key())
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(interval)
+            .query_async(&mut self.redis_conn)
+            .await?;
+        if reserved.is_some() { return Ok(None) }
+
+        let ttl: i64 = self.redis_conn.ttl(self.last_post_key()).await?;
+        Ok(Some(ttl.max(1) as u64))
+    }
+
+    /// Gives back the interval reserved for an ad that didn't get published after all.
+    pub async fn release_posting_cooldown(&mut self) -> RedisResult<()> {
+        self.redis_conn.del(self.last_post_key()).await
+    }
+
+    /// Forbids reposting the ad for [`REPOST_COOLDOWN_SECS`].
+    pub async fn set_repost_cooldown(&mut self, message_id: MessageId) -> RedisResult<()> {
+        self.redis_conn.set_ex(self.repost_key(message_id), 1, REPOST_COOLDOWN_SECS).await
+    }
+
+    /// Returns seconds left until the ad can be reposted again, if any.
+    pub async fn repost_cooldown(&mut self, message_id: MessageId) -> RedisResult<Option<u64>> {
+        let ttl: i64 = self.redis_conn.ttl(self.repost_key(message_id)).await?;
+        Ok(if ttl > 0 { Some(ttl as u64) } else { None })
+    }
 }
 
 impl Display for SwappyUser<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<a href=\"{}\">{}</a>",
-               self.tg_user.url(),
-               self.tg_user.full_name().trim()
-        )
+        f.write_str(&user_link(&self.tg_user))
+    }
+}
+
+/// Name of the user as an HTML link to them. Names are up to the users, so they are escaped.
+fn user_link(user: &User) -> String {
+    format!("<a href=\"{}\">{}</a>", user.url(), html::escape(user.full_name().trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{User, UserId};
+    use super::user_link;
+
+    #[test]
+    fn name_markup_is_escaped() {
+        let user = User {
+            id: UserId(195125422),
+            is_bot: false,
+            first_name: "<b>Вася</b>".to_string(),
+            last_name: Some("&<a href=\"https://evil.example\">".to_string()),
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+
+        assert_eq!(
+            user_link(&user),
+            "<a href=\"tg://user/?id=195125422\">&lt;b&gt;Вася&lt;/b&gt; \
+            &amp;&lt;a href=\"https://evil.example\"&gt;</a>",
+        );
     }
 }
\ No newline at end of file